@@ -0,0 +1,553 @@
+/*
+TextFrame
+  by Maarten van Gompel <proycon@anaproy.nl>
+  Digital Infrastructure, KNAW Humanities Cluster
+  licensed under the GNU General Public Licence v3
+*/
+
+//! Retrieval of text along with its surrounding context, such as keyword-in-context (KWIC) concordances
+
+use std::fs::File;
+
+use crate::{Error, TextFile};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// The unit in which an amount of context is expressed
+pub enum ContextUnit {
+    /// Unicode characters
+    #[default]
+    Chars,
+
+    /// Tokens, i.e. runs of non-whitespace characters. Whitespace adjacent to the range is included in the context but not counted.
+    Tokens,
+
+    /// Lines, like `grep -B`/`-A`: the lines the range begins and ends on are always completed, the amount of context
+    /// is the number of further lines before and after them. This requires a line index, `Error::NoLineIndex` is returned otherwise.
+    Lines,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Options for extracting concordance lines
+pub struct ConcordanceOptions {
+    width: usize,
+    unit: ContextUnit,
+    clip_lines: bool,
+}
+
+impl Default for ConcordanceOptions {
+    fn default() -> Self {
+        Self {
+            width: 40,
+            unit: ContextUnit::Chars,
+            clip_lines: false,
+        }
+    }
+}
+
+impl ConcordanceOptions {
+    /// Set the amount of context on either side of the range (default: 40)
+    pub fn with_width(mut self, width: usize) -> Self {
+        self.width = width;
+        self
+    }
+
+    /// Set the unit in which the width is expressed (default: characters)
+    pub fn with_unit(mut self, unit: ContextUnit) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    /// Clip the context at the start of the line the range begins on and at the end of the line it ends on.
+    /// This requires a line index, `Error::NoLineIndex` is returned otherwise.
+    pub fn with_clip_lines(mut self, value: bool) -> Self {
+        self.clip_lines = value;
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// A text fragment along with the text before and after it, as returned by `TextFile::get_or_load_with_context()`. Offsets are absolute character offsets.
+pub struct TextWithContext<'a> {
+    /// The text before the range
+    pub left: &'a str,
+
+    /// The text of the range itself
+    pub text: &'a str,
+
+    /// The text after the range
+    pub right: &'a str,
+
+    /// Where the left context begins
+    pub leftbegin: usize,
+
+    /// Where the range begins (and the left context ends)
+    pub begin: usize,
+
+    /// Where the range ends (non-inclusive, and the right context begins)
+    pub end: usize,
+
+    /// Where the right context ends (non-inclusive)
+    pub rightend: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A single concordance line: a range along with the text to its left and right. Offsets are absolute character offsets.
+pub struct ConcordanceLine {
+    /// The text before the range
+    pub left: String,
+
+    /// The text of the range itself
+    pub keyword: String,
+
+    /// The text after the range
+    pub right: String,
+
+    /// Where the left context begins
+    pub leftbegin: usize,
+
+    /// Where the range begins (and the left context ends)
+    pub begin: usize,
+
+    /// Where the range ends (non-inclusive, and the right context begins)
+    pub end: usize,
+
+    /// Where the right context ends (non-inclusive)
+    pub rightend: usize,
+}
+
+/// The initial number of characters to load when looking for tokens, per requested token
+const TOKEN_WINDOW: usize = 16;
+
+impl TextFile {
+    /// Returns a text fragment along with some context before and after it, clamped at the edges of the text file.
+    /// The fragment and its context will be loaded from disk into memory if needed.
+    ///
+    /// * `begin` - The begin offset in unicode character points (0-indexed). If negative, it is interpreted relative to the end of the text.
+    /// * `end` - The end offset in unicode character points (0-indexed, non-inclusive). If 0 or negative, it is interpreted relative to the end of the text.
+    /// * `before` - The amount of context before the fragment
+    /// * `after` - The amount of context after the fragment
+    /// * `unit` - The unit in which the amounts of context are expressed
+    pub fn get_or_load_with_context(
+        &mut self,
+        begin: isize,
+        end: isize,
+        before: usize,
+        after: usize,
+        unit: ContextUnit,
+    ) -> Result<TextWithContext<'_>, Error> {
+        let (begin, end) = self.absolute_pos(begin, end)?;
+        self.get_or_load_with_context_abs(begin, end, before, after, unit)
+    }
+
+    /// Returns a text fragment given absolute character offsets (end is non-inclusive) along with context, like `get_or_load_with_context()`
+    pub(crate) fn get_or_load_with_context_abs(
+        &mut self,
+        begin: usize,
+        end: usize,
+        before: usize,
+        after: usize,
+        unit: ContextUnit,
+    ) -> Result<TextWithContext<'_>, Error> {
+        let len = self.len();
+        self.get_or_load_with_context_within(begin, end, before, after, unit, (0, len))
+    }
+
+    /// Like `get_or_load_with_context_abs()`, but the context does not go beyond the given limits (absolute character offsets)
+    fn get_or_load_with_context_within(
+        &mut self,
+        begin: usize,
+        end: usize,
+        before: usize,
+        after: usize,
+        unit: ContextUnit,
+        (lowerlimit, upperlimit): (usize, usize),
+    ) -> Result<TextWithContext<'_>, Error> {
+        self.check_bounds(begin, end)?;
+        let leftbegin = self.context_begin(begin, before, unit, lowerlimit)?;
+        let rightend = self.context_end(begin, end, after, unit, upperlimit)?;
+        let beginbyte = self.chars_to_bytes(begin)? - self.chars_to_bytes(leftbegin)?;
+        let endbyte = self.chars_to_bytes(end)? - self.chars_to_bytes(leftbegin)?;
+        let text = self.get_or_load_abs(leftbegin, rightend)?;
+        Ok(TextWithContext {
+            left: &text[..beginbyte],
+            text: &text[beginbyte..endbyte],
+            right: &text[endbyte..],
+            leftbegin,
+            begin,
+            end,
+            rightend,
+        })
+    }
+
+    /// Extracts keyword-in-context (KWIC) concordance lines for the given character ranges (absolute offsets, end non-inclusive),
+    /// for instance search results. Only the needed text around each range is loaded from disk into memory.
+    pub fn concordance(
+        &mut self,
+        ranges: &[(usize, usize)],
+        options: ConcordanceOptions,
+    ) -> Result<Vec<ConcordanceLine>, Error> {
+        ranges
+            .iter()
+            .map(|(begin, end)| self.concordance_line(*begin, *end, options))
+            .collect()
+    }
+
+    fn concordance_line(
+        &mut self,
+        begin: usize,
+        end: usize,
+        options: ConcordanceOptions,
+    ) -> Result<ConcordanceLine, Error> {
+        self.check_bounds(begin, end)?;
+        let (lowerlimit, upperlimit) = if options.clip_lines {
+            let (linebegin, _) = self.line_chars(self.char_to_line(begin)?)?;
+            // a range that ends with a newline ends on the line of that newline
+            let (_, lineend) = self.line_chars(self.char_to_line(end.max(begin + 1) - 1)?)?;
+            (linebegin, lineend.max(end))
+        } else {
+            (0, self.len())
+        };
+        let context = self.get_or_load_with_context_within(
+            begin,
+            end,
+            options.width,
+            options.width,
+            options.unit,
+            (lowerlimit, upperlimit),
+        )?;
+        Ok(ConcordanceLine {
+            left: context.left.to_string(),
+            keyword: context.text.to_string(),
+            right: context.right.to_string(),
+            leftbegin: context.leftbegin,
+            begin,
+            end,
+            rightend: context.rightend,
+        })
+    }
+
+    /// Checks whether an absolute character range is valid
+    pub(crate) fn check_bounds(&self, begin: usize, end: usize) -> Result<(), Error> {
+        self.check_order(begin, end)?;
+        if end > self.len() {
+            return Err(Error::OutOfBoundsError {
+                begin: begin as isize,
+                end: end as isize,
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns where the context of the given width before `begin` begins, not going beyond `limit`.
+    /// Text that is only needed to find the boundary is read from disk without keeping it in memory.
+    fn context_begin(
+        &self,
+        begin: usize,
+        width: usize,
+        unit: ContextUnit,
+        limit: usize,
+    ) -> Result<usize, Error> {
+        match unit {
+            ContextUnit::Chars => Ok(begin.saturating_sub(width).max(limit)),
+            ContextUnit::Tokens => {
+                if width == 0 {
+                    return Ok(begin);
+                }
+                // read an increasingly large window until it holds enough tokens
+                let mut file = File::open(self.path.as_path()).map_err(Error::IOError)?;
+                let mut window = width.saturating_mul(TOKEN_WINDOW);
+                loop {
+                    let from = begin.saturating_sub(window).max(limit);
+                    let mut count = 0;
+                    let mut intoken = false;
+                    let mut pos = begin;
+                    for c in self.read_chars(&mut file, from, begin)?.chars().rev() {
+                        if !c.is_whitespace() {
+                            intoken = true;
+                        } else if intoken {
+                            count += 1;
+                            if count == width {
+                                return Ok(pos);
+                            }
+                            intoken = false;
+                        }
+                        pos -= 1;
+                    }
+                    if from == limit {
+                        return Ok(from);
+                    }
+                    window = window.saturating_mul(2);
+                }
+            }
+            ContextUnit::Lines => {
+                let line = self.char_to_line(begin)?.saturating_sub(width);
+                let (linebegin, _) = self.line_chars(line)?;
+                Ok(linebegin.max(limit))
+            }
+        }
+    }
+
+    /// Returns where the context of the given width after the range ends (non-inclusive), not going beyond `limit`.
+    /// Text that is only needed to find the boundary is read from disk without keeping it in memory.
+    fn context_end(
+        &self,
+        begin: usize,
+        end: usize,
+        width: usize,
+        unit: ContextUnit,
+        limit: usize,
+    ) -> Result<usize, Error> {
+        match unit {
+            ContextUnit::Chars => Ok(end.saturating_add(width).min(limit)),
+            ContextUnit::Tokens => {
+                if width == 0 {
+                    return Ok(end);
+                }
+                let mut file = File::open(self.path.as_path()).map_err(Error::IOError)?;
+                let mut window = width.saturating_mul(TOKEN_WINDOW);
+                loop {
+                    let to = end.saturating_add(window).min(limit);
+                    let mut count = 0;
+                    let mut intoken = false;
+                    for (pos, c) in (end..).zip(self.read_chars(&mut file, end, to)?.chars()) {
+                        if !c.is_whitespace() {
+                            intoken = true;
+                        } else if intoken {
+                            count += 1;
+                            if count == width {
+                                return Ok(pos);
+                            }
+                            intoken = false;
+                        }
+                    }
+                    if to == limit {
+                        return Ok(to);
+                    }
+                    window = window.saturating_mul(2);
+                }
+            }
+            ContextUnit::Lines => {
+                // a range that ends with a newline ends on the line of that newline
+                let line = self.char_to_line(end.max(begin + 1) - 1)?;
+                let lastline = self.positionindex.lines.len() - 1;
+                let (_, lineend) = self.line_chars(line.saturating_add(width).min(lastline))?;
+                Ok(lineend.max(end).min(limit))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use crate::TextFileMode;
+
+    #[test]
+    pub fn test035_concordance_chars() {
+        let file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let ranges: Vec<(usize, usize)> = textfile
+            .find_iter("dignity")
+            .map(|m| m.expect("match"))
+            .collect();
+        let lines = textfile
+            .concordance(&ranges, ConcordanceOptions::default().with_width(10))
+            .unwrap();
+        assert_eq!(lines.len(), ranges.len());
+        let line = &lines[0];
+        assert_eq!(line.keyword, "dignity");
+        assert_eq!(line.left.chars().count(), 10);
+        assert_eq!(line.right.chars().count(), 10);
+        assert_eq!(line.leftbegin, line.begin - 10);
+        assert_eq!(line.rightend, line.end + 10);
+        assert_eq!(line.left, EXAMPLE_ASCII_TEXT[line.leftbegin..line.begin]);
+        assert_eq!(line.right, EXAMPLE_ASCII_TEXT[line.end..line.rightend]);
+
+        //clamped at the start of the file
+        let lines = textfile
+            .concordance(&[(1, 8)], ConcordanceOptions::default().with_width(10))
+            .unwrap();
+        assert_eq!(lines[0].left, "\n");
+        assert_eq!(lines[0].leftbegin, 0);
+        assert_eq!(lines[0].right, " 1\n\nAll hu");
+    }
+
+    #[test]
+    pub fn test035_concordance_tokens() {
+        let file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let ranges: Vec<(usize, usize)> = textfile
+            .find_iter("dignity")
+            .map(|m| m.expect("match"))
+            .collect();
+        let lines = textfile
+            .concordance(
+                &ranges[..1],
+                ConcordanceOptions::default()
+                    .with_width(2)
+                    .with_unit(ContextUnit::Tokens),
+            )
+            .unwrap();
+        assert_eq!(lines[0].left, "equal in ");
+        assert_eq!(lines[0].right, " and rights.");
+    }
+
+    #[test]
+    pub fn test035_concordance_frames() {
+        let file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let ranges: Vec<(usize, usize)> = textfile
+            .find_iter("Article")
+            .map(|m| m.expect("match"))
+            .collect();
+        for unit in [ContextUnit::Chars, ContextUnit::Tokens] {
+            textfile.frames.clear();
+            textfile.frametable.clear();
+            let lines = textfile
+                .concordance(
+                    &ranges,
+                    ConcordanceOptions::default().with_width(20).with_unit(unit),
+                )
+                .unwrap();
+            assert_eq!(lines.len(), ranges.len());
+            //at most one frame per concordance line is kept
+            assert!(textfile.frames.len() <= ranges.len());
+        }
+    }
+
+    #[test]
+    pub fn test035_concordance_clip_lines() {
+        let file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let lines = textfile
+            .concordance(
+                &[(1, 8)],
+                ConcordanceOptions::default()
+                    .with_width(10)
+                    .with_clip_lines(true),
+            )
+            .unwrap();
+        assert_eq!(lines[0].left, "");
+        assert_eq!(lines[0].right, " 1");
+        assert_eq!(lines[0].rightend, 10);
+        let lines = textfile
+            .concordance(
+                &[(1, 8)],
+                ConcordanceOptions::default()
+                    .with_width(5)
+                    .with_unit(ContextUnit::Tokens)
+                    .with_clip_lines(true),
+            )
+            .unwrap();
+        assert_eq!(lines[0].right, " 1");
+
+        //no line index
+        let mut textfile =
+            TextFile::new(file.path(), None, TextFileMode::NoLineIndex).expect("file must load");
+        assert!(matches!(
+            textfile.concordance(
+                &[(1, 8)],
+                ConcordanceOptions::default().with_clip_lines(true)
+            ),
+            Err(Error::NoLineIndex)
+        ));
+    }
+
+    #[test]
+    pub fn test036_with_context_chars() {
+        let file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let begin = EXAMPLE_ASCII_TEXT.find("dignity").unwrap();
+        let end = begin + 7;
+        let result = textfile
+            .get_or_load_with_context(begin as isize, end as isize, 9, 11, ContextUnit::Chars)
+            .unwrap();
+        assert_eq!(result.left, "equal in ");
+        assert_eq!(result.text, "dignity");
+        assert_eq!(result.right, " and rights");
+        assert_eq!(
+            (result.leftbegin, result.begin, result.end, result.rightend),
+            (begin - 9, begin, end, end + 11)
+        );
+
+        //clamped at the edges of the file
+        let result = textfile
+            .get_or_load_with_context(1, 8, 100, 3, ContextUnit::Chars)
+            .unwrap();
+        assert_eq!(
+            (result.left, result.text, result.right),
+            ("\n", "Article", " 1\n")
+        );
+        assert_eq!(result.leftbegin, 0);
+        let len = textfile.len();
+        let result = textfile
+            .get_or_load_with_context(-2, 0, 3, 100, ContextUnit::Chars)
+            .unwrap();
+        assert_eq!(result.right, "");
+        assert_eq!(result.rightend, len);
+        assert_eq!(result.begin, len - 2);
+        assert!(textfile
+            .get_or_load_with_context(0, len as isize + 1, 1, 1, ContextUnit::Chars)
+            .is_err());
+    }
+
+    #[test]
+    pub fn test036_with_context_unbounded() {
+        let file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let len = textfile.len();
+        for unit in [ContextUnit::Chars, ContextUnit::Tokens, ContextUnit::Lines] {
+            let result = textfile
+                .get_or_load_with_context(1, 8, usize::MAX, usize::MAX, unit)
+                .unwrap();
+            assert_eq!((result.leftbegin, result.rightend), (0, len), "{:?}", unit);
+            assert_eq!(result.text, "Article");
+            let result = textfile
+                .get_or_load_with_context(0, 2, 0, usize::MAX, unit)
+                .unwrap();
+            assert_eq!((result.leftbegin, result.rightend), (0, len), "{:?}", unit);
+        }
+    }
+
+    #[test]
+    pub fn test036_with_context_lines() {
+        let file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let begin = EXAMPLE_ASCII_TEXT.find("dignity").unwrap() as isize;
+        let result = textfile
+            .get_or_load_with_context(begin, begin + 7, 0, 0, ContextUnit::Lines)
+            .unwrap();
+        assert_eq!(result.left, "All human beings are born free and equal in ");
+        assert!(result.right.starts_with(" and rights."));
+        assert!(result.right.ends_with("brotherhood."));
+        //one more line on either side, both are empty here
+        let result = textfile
+            .get_or_load_with_context(begin, begin + 7, 1, 1, ContextUnit::Lines)
+            .unwrap();
+        assert_eq!(
+            result.left,
+            "\nAll human beings are born free and equal in "
+        );
+        assert!(result.right.ends_with("brotherhood.\n"));
+        //clamped at the start of the file
+        let result = textfile
+            .get_or_load_with_context(1, 8, 5, 0, ContextUnit::Lines)
+            .unwrap();
+        assert_eq!((result.left, result.right), ("\n", " 1"));
+
+        let mut textfile =
+            TextFile::new(file.path(), None, TextFileMode::NoLineIndex).expect("file must load");
+        assert!(matches!(
+            textfile.get_or_load_with_context(1, 8, 1, 1, ContextUnit::Lines),
+            Err(Error::NoLineIndex)
+        ));
+    }
+}
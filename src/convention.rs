@@ -0,0 +1,350 @@
+/*
+TextFrame
+  by Maarten van Gompel <proycon@anaproy.nl>
+  Digital Infrastructure, KNAW Humanities Cluster
+  licensed under the GNU General Public Licence v3
+*/
+
+//! Adapter for querying a text file with offsets in another convention, e.g. 1-based and/or end-inclusive offsets as used by many external tools
+
+use crate::{Error, Offset, TextFile};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+/// An offset convention. The default is the convention of this library: 0-based offsets with a non-inclusive end.
+/// Unlike the methods on `TextFile`, there is no special interpretation of 0, use `Offset::FromEnd` for offsets relative to the end of the text.
+pub struct Convention {
+    one_based: bool,
+    end_inclusive: bool,
+}
+
+impl Convention {
+    /// 1-based offsets with an inclusive end, a common convention in annotation formats and bioinformatics
+    pub fn one_based_inclusive() -> Self {
+        Self {
+            one_based: true,
+            end_inclusive: true,
+        }
+    }
+
+    /// Set whether offsets (and line numbers) are 1-based rather than 0-based
+    pub fn with_one_based(mut self, value: bool) -> Self {
+        self.one_based = value;
+        self
+    }
+
+    /// Set whether the end of a range is inclusive rather than non-inclusive
+    pub fn with_end_inclusive(mut self, value: bool) -> Self {
+        self.end_inclusive = value;
+        self
+    }
+
+    pub fn one_based(&self) -> bool {
+        self.one_based
+    }
+
+    pub fn end_inclusive(&self) -> bool {
+        self.end_inclusive
+    }
+
+    fn base(&self) -> usize {
+        if self.one_based {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// Converts a single offset in this convention to an absolute 0-based offset
+    pub fn to_absolute_pos(&self, pos: usize) -> Result<usize, Error> {
+        pos.checked_sub(self.base()).ok_or(Error::OutOfBoundsError {
+            begin: pos as isize,
+            end: 0,
+        })
+    }
+
+    /// Converts an absolute 0-based offset to a single offset in this convention
+    pub fn from_absolute_pos(&self, pos: usize) -> usize {
+        pos + self.base()
+    }
+
+    /// Converts a range in this convention to absolute 0-based offsets with a non-inclusive end.
+    /// With an inclusive end, an empty range is expressed by an end that is one before the begin.
+    pub fn to_absolute_range(&self, begin: usize, end: usize) -> Result<(usize, usize), Error> {
+        let outofbounds = Error::OutOfBoundsError {
+            begin: begin as isize,
+            end: end as isize,
+        };
+        let absbegin = self.to_absolute_pos(begin)?;
+        let absend = if self.end_inclusive {
+            end.checked_add(1)
+                .ok_or(Error::OutOfBoundsError {
+                    begin: begin as isize,
+                    end: end as isize,
+                })?
+                .checked_sub(self.base())
+        } else {
+            end.checked_sub(self.base())
+        };
+        match absend {
+            Some(absend) if absend >= absbegin => Ok((absbegin, absend)),
+            _ => Err(outofbounds),
+        }
+    }
+
+    /// Converts an offset in this convention to one relative to the start, given the total length (in characters or lines).
+    /// `FromEnd(n)` refers to the same position as `FromStart(end - n)` does, where `end` is the offset just after the text,
+    /// or that of the last character (or line) if `is_end` is set and the end is inclusive. So `FromEnd(0)` is the end of the text either way.
+    fn resolve_pos(&self, offset: Offset, len: usize, is_end: bool) -> Result<usize, Error> {
+        match offset {
+            Offset::FromStart(offset) => Ok(offset),
+            Offset::FromEnd(offset) => offset
+                .checked_add((is_end && self.end_inclusive) as usize)
+                .and_then(|offset| (len + self.base()).checked_sub(offset))
+                .ok_or(Error::OutOfBoundsError {
+                    begin: -(offset as isize),
+                    end: 0,
+                }),
+        }
+    }
+
+    /// Converts a range in this convention that may be relative to the end to absolute 0-based offsets with a non-inclusive end,
+    /// given the total length (in characters or lines).
+    fn resolve_range(
+        &self,
+        begin: Offset,
+        end: Offset,
+        len: usize,
+    ) -> Result<(usize, usize), Error> {
+        self.to_absolute_range(
+            self.resolve_pos(begin, len, false)?,
+            self.resolve_pos(end, len, true)?,
+        )
+    }
+
+    /// Converts absolute 0-based offsets with a non-inclusive end to a range in this convention.
+    /// Returns an `Error::OutOfBoundsError` for an empty range at the very beginning, as that can not be expressed in a 0-based end-inclusive convention.
+    pub fn from_absolute_range(&self, begin: usize, end: usize) -> Result<(usize, usize), Error> {
+        if begin > end {
+            return Err(Error::OutOfBoundsError {
+                begin: begin as isize,
+                end: end as isize,
+            });
+        }
+        let end = if self.end_inclusive {
+            (end + self.base())
+                .checked_sub(1)
+                .ok_or(Error::OutOfBoundsError {
+                    begin: begin as isize,
+                    end: end as isize,
+                })?
+        } else {
+            end + self.base()
+        };
+        Ok((begin + self.base(), end))
+    }
+}
+
+/// A view on a text file in which all offsets and line numbers are expressed in a particular convention.
+/// Obtain one via `TextFile::with_convention()`. Offsets are either a `usize` or an `Offset`, i.e. `FromStart(n)` or `FromEnd(n)`.
+/// For example, `view.get(FromEnd(10), FromEnd(0))` returns the last ten characters in any convention.
+pub struct ConventionView<'a> {
+    textfile: &'a mut TextFile,
+    convention: Convention,
+}
+
+impl TextFile {
+    /// Returns a view on this text file in which all offsets and line numbers are expressed in the given convention
+    pub fn with_convention(&mut self, convention: Convention) -> ConventionView<'_> {
+        ConventionView {
+            textfile: self,
+            convention,
+        }
+    }
+}
+
+impl ConventionView<'_> {
+    pub fn convention(&self) -> Convention {
+        self.convention
+    }
+
+    /// Returns the underlying text file
+    pub fn textfile(&self) -> &TextFile {
+        self.textfile
+    }
+
+    /// Converts a character range in this convention to absolute 0-based offsets with a non-inclusive end
+    fn chars_range(&self, begin: Offset, end: Offset) -> Result<(usize, usize), Error> {
+        self.convention
+            .resolve_range(begin, end, self.textfile.positionindex.charsize)
+    }
+
+    /// Converts a line range in this convention to absolute 0-based line numbers with a non-inclusive end
+    fn lines_range(&self, begin: Offset, end: Offset) -> Result<(usize, usize), Error> {
+        self.convention
+            .resolve_range(begin, end, self.textfile.positionindex.lines.len())
+    }
+
+    /// Returns a text fragment by character offsets in this convention.
+    /// The fragment must already be in memory or an Error::NotLoaded will be returned.
+    pub fn get(&self, begin: impl Into<Offset>, end: impl Into<Offset>) -> Result<&str, Error> {
+        let (begin, end) = self.chars_range(begin.into(), end.into())?;
+        self.textfile.get_range(begin..end)
+    }
+
+    /// Returns a text fragment by character offsets in this convention, the fragment will be loaded from disk into memory if needed.
+    pub fn get_or_load(
+        &mut self,
+        begin: impl Into<Offset>,
+        end: impl Into<Offset>,
+    ) -> Result<&str, Error> {
+        let (begin, end) = self.chars_range(begin.into(), end.into())?;
+        self.textfile.get_or_load_range(begin..end)
+    }
+
+    /// Returns a text fragment by line numbers in this convention, trailing newline characters will always be returned.
+    /// The fragment must already be in memory or an Error::NotLoaded will be returned.
+    ///
+    /// This will return Error::NoLineIndex if no line index was computed.
+    pub fn get_lines(
+        &self,
+        begin: impl Into<Offset>,
+        end: impl Into<Offset>,
+    ) -> Result<&str, Error> {
+        let (begin, end) = self.lines_range(begin.into(), end.into())?;
+        self.textfile.get_lines_range(begin..end)
+    }
+
+    /// Returns a text fragment by line numbers in this convention, trailing newline characters will always be returned.
+    /// The fragment will be loaded from disk into memory if needed.
+    ///
+    /// This will return Error::NoLineIndex if no line index was computed.
+    pub fn get_or_load_lines(
+        &mut self,
+        begin: impl Into<Offset>,
+        end: impl Into<Offset>,
+    ) -> Result<&str, Error> {
+        let (begin, end) = self.lines_range(begin.into(), end.into())?;
+        self.textfile.get_or_load_lines_range(begin..end)
+    }
+
+    /// Returns the line number that the given character position is on, both in this convention.
+    /// This will return an `Error::NoLineIndex` if no line index was computed/loaded.
+    pub fn char_to_line(&self, charpos: impl Into<Offset>) -> Result<usize, Error> {
+        let charpos = self.convention.resolve_pos(
+            charpos.into(),
+            self.textfile.positionindex.charsize,
+            false,
+        )?;
+        let charpos = self.convention.to_absolute_pos(charpos)?;
+        self.textfile
+            .char_to_line(charpos)
+            .map(|line| self.convention.from_absolute_pos(line))
+    }
+
+    /// Returns the character position at which the given line begins, both in this convention.
+    /// This will return an `Error::NoLineIndex` if no line index was computed/loaded.
+    pub fn line_to_char(&self, line: impl Into<Offset>) -> Result<usize, Error> {
+        let line = self.convention.resolve_pos(
+            line.into(),
+            self.textfile.positionindex.lines.len(),
+            false,
+        )?;
+        let line = self.convention.to_absolute_pos(line)?;
+        let (begin, _) = self.textfile.line_chars(line)?;
+        Ok(self.convention.from_absolute_pos(begin))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use Offset::FromEnd;
+
+    #[test]
+    pub fn test027_convention_ranges() {
+        let convention = Convention::one_based_inclusive();
+        assert_eq!(convention.to_absolute_range(1, 9).unwrap(), (0, 9));
+        assert_eq!(convention.to_absolute_range(5, 4).unwrap(), (4, 4));
+        assert!(convention.to_absolute_range(0, 4).is_err());
+        assert!(convention.to_absolute_range(5, 3).is_err());
+        assert!(matches!(
+            convention.to_absolute_range(1, usize::MAX),
+            Err(Error::OutOfBoundsError { .. })
+        ));
+        assert_eq!(convention.from_absolute_range(0, 9).unwrap(), (1, 9));
+        let convention = Convention::default().with_end_inclusive(true);
+        assert_eq!(convention.to_absolute_range(0, 0).unwrap(), (0, 1));
+        assert!(convention.from_absolute_range(0, 0).is_err());
+        let convention = Convention::default();
+        assert_eq!(convention.to_absolute_range(0, 0).unwrap(), (0, 0));
+        assert_eq!(convention.from_absolute_range(3, 5).unwrap(), (3, 5));
+    }
+
+    #[test]
+    pub fn test027_convention_view() {
+        let file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let mut view = textfile.with_convention(Convention::one_based_inclusive());
+        //the text starts with a newline, so "Article 1" is at 2..=10
+        assert_eq!(
+            view.get_or_load(2, 10).expect("text should exist"),
+            "Article 1"
+        );
+        assert_eq!(view.get(2, 10).expect("text should exist"), "Article 1");
+        assert_eq!(
+            view.get_or_load_lines(2, 2).expect("text should exist"),
+            "Article 1\n"
+        );
+        assert_eq!(view.char_to_line(2).unwrap(), 2);
+        assert_eq!(view.line_to_char(2).unwrap(), 2);
+        assert!(view.get_or_load(0, 10).is_err());
+        assert!(view.get_or_load(900, 1000).is_err());
+        //no interpretation relative to the end
+        assert_eq!(view.get_or_load(2, 1).expect("text should exist"), "");
+    }
+
+    #[test]
+    pub fn test027_convention_view_from_end() {
+        let file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let last = textfile
+            .get_or_load(-10, 0)
+            .expect("text should exist")
+            .to_string();
+        for convention in [
+            Convention::default(),
+            Convention::one_based_inclusive(),
+            Convention::default().with_end_inclusive(true),
+            Convention::default().with_one_based(true),
+        ] {
+            let mut view = textfile.with_convention(convention);
+            assert_eq!(
+                view.get_or_load(FromEnd(10), FromEnd(0))
+                    .expect("text should exist"),
+                last,
+                "{:?}",
+                convention
+            );
+            assert_eq!(
+                view.get_or_load(FromEnd(10), FromEnd(9))
+                    .expect("text should exist"),
+                &last[..1]
+            );
+            assert_eq!(view.get(FromEnd(0), FromEnd(0)).expect("empty"), "");
+            assert!(view.get_or_load(FromEnd(1000), FromEnd(0)).is_err());
+        }
+        let numlines = textfile.positionindex.lines.len();
+        let view = textfile.with_convention(Convention::one_based_inclusive());
+        assert_eq!(
+            view.char_to_line(FromEnd(1)).unwrap(),
+            view.char_to_line(914).unwrap()
+        );
+        assert_eq!(
+            view.line_to_char(FromEnd(1)).unwrap(),
+            view.line_to_char(numlines).unwrap()
+        );
+    }
+}
@@ -0,0 +1,468 @@
+/*
+TextFrame
+  by Maarten van Gompel <proycon@anaproy.nl>
+  Digital Infrastructure, KNAW Humanities Cluster
+  licensed under the GNU General Public Licence v3
+*/
+
+//! Fragment identifiers for plain text as specified in RFC 5147, e.g. `#char=10,20` or `#line=3,5;length=914`.
+//!
+//! Characters are counted as they are stored in the file, no line ending normalisation is applied (a CRLF counts as two characters).
+//! Positions beyond the end of the text refer to the end of the text.
+
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::str::FromStr;
+
+use crate::{Error, TextFile};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// The scheme of a fragment identifier, determines the unit of the positions
+pub enum FragmentScheme {
+    /// Positions count characters (`char=`)
+    Char,
+
+    /// Positions count lines (`line=`)
+    Line,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+/// An integrity check that is part of a fragment identifier
+pub enum IntegrityCheck {
+    /// The length of the text (`length=`). Without a charset, this is the number of characters,
+    /// with a charset it is the number of bytes in that encoding (only UTF-8 and US-ASCII are supported).
+    Length {
+        length: usize,
+        charset: Option<String>,
+    },
+
+    /// The MD5 hash of the text (`md5=`), computed over the bytes in the file
+    Md5 {
+        digest: [u8; 16],
+        charset: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+/// A parsed RFC 5147 fragment identifier
+pub struct TextFragment {
+    scheme: FragmentScheme,
+    begin: usize,
+    /// The end position (non-inclusive), `None` means until the end of the text
+    end: Option<usize>,
+    integrity: Vec<IntegrityCheck>,
+}
+
+impl TextFragment {
+    /// Creates a new fragment identifier for a range, an `end` of `None` means until the end of the text.
+    /// If `begin` and `end` are equal, the fragment identifies a single position.
+    pub fn new(scheme: FragmentScheme, begin: usize, end: Option<usize>) -> Result<Self, Error> {
+        if let Some(end) = end {
+            if begin > end {
+                return Err(Error::OutOfBoundsError {
+                    begin: begin as isize,
+                    end: end as isize,
+                });
+            }
+        }
+        Ok(Self {
+            scheme,
+            begin,
+            end,
+            integrity: Vec::new(),
+        })
+    }
+
+    /// Adds an integrity check
+    pub fn with_integrity(mut self, check: IntegrityCheck) -> Self {
+        self.integrity.push(check);
+        self
+    }
+
+    /// Parses a fragment identifier, with or without the leading `#`
+    pub fn parse(fragment: &str) -> Result<Self, Error> {
+        let fragment = fragment.strip_prefix('#').unwrap_or(fragment);
+        let mut parts = fragment.split(';');
+        let scheme_part = parts.next().unwrap_or_default();
+        let (scheme, positions) = if let Some(positions) = scheme_part.strip_prefix("char=") {
+            (FragmentScheme::Char, positions)
+        } else if let Some(positions) = scheme_part.strip_prefix("line=") {
+            (FragmentScheme::Line, positions)
+        } else {
+            return Err(Error::InvalidFragment(format!(
+                "expected char= or line=, got {}",
+                scheme_part
+            )));
+        };
+        let (begin, end) = match positions.split_once(',') {
+            None => {
+                let position = parse_number(positions)?;
+                (position, Some(position))
+            }
+            Some(("", "")) => {
+                return Err(Error::InvalidFragment(
+                    "range requires at least one position".to_string(),
+                ))
+            }
+            Some(("", end)) => (0, Some(parse_number(end)?)),
+            Some((begin, "")) => (parse_number(begin)?, None),
+            Some((begin, end)) => (parse_number(begin)?, Some(parse_number(end)?)),
+        };
+        if end.is_some_and(|end| end < begin) {
+            return Err(Error::InvalidFragment(format!(
+                "end position before begin position: {}",
+                positions
+            )));
+        }
+        let mut textfragment = Self::new(scheme, begin, end)?;
+        for part in parts {
+            let (value, charset) = match part.split_once(',') {
+                Some((value, charset)) => (value, Some(charset.to_string())),
+                None => (part, None),
+            };
+            let check = if let Some(length) = value.strip_prefix("length=") {
+                IntegrityCheck::Length {
+                    length: parse_number(length)?,
+                    charset,
+                }
+            } else if let Some(digest) = value.strip_prefix("md5=") {
+                IntegrityCheck::Md5 {
+                    digest: parse_md5(digest)?,
+                    charset,
+                }
+            } else {
+                return Err(Error::InvalidFragment(format!(
+                    "expected length= or md5=, got {}",
+                    value
+                )));
+            };
+            textfragment.integrity.push(check);
+        }
+        Ok(textfragment)
+    }
+
+    pub fn scheme(&self) -> FragmentScheme {
+        self.scheme
+    }
+
+    pub fn begin(&self) -> usize {
+        self.begin
+    }
+
+    /// The end position (non-inclusive), `None` means until the end of the text
+    pub fn end(&self) -> Option<usize> {
+        self.end
+    }
+
+    pub fn integrity(&self) -> &[IntegrityCheck] {
+        &self.integrity
+    }
+}
+
+impl FromStr for TextFragment {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for TextFragment {
+    /// Formats the fragment identifier (without the leading `#`)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.scheme {
+            FragmentScheme::Char => write!(f, "char=")?,
+            FragmentScheme::Line => write!(f, "line=")?,
+        }
+        match self.end {
+            Some(end) if end == self.begin => write!(f, "{}", self.begin)?,
+            Some(end) => write!(f, "{},{}", self.begin, end)?,
+            None => write!(f, "{},", self.begin)?,
+        }
+        for check in self.integrity.iter() {
+            let charset = match check {
+                IntegrityCheck::Length { length, charset } => {
+                    write!(f, ";length={}", length)?;
+                    charset
+                }
+                IntegrityCheck::Md5 { digest, charset } => {
+                    write!(f, ";md5=")?;
+                    for byte in digest {
+                        write!(f, "{:02x}", byte)?;
+                    }
+                    charset
+                }
+            };
+            if let Some(charset) = charset {
+                write!(f, ",{}", charset)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_number(s: &str) -> Result<usize, Error> {
+    if s.is_empty() || !s.bytes().all(|c| c.is_ascii_digit()) {
+        return Err(Error::InvalidFragment(format!("invalid number: {}", s)));
+    }
+    s.parse()
+        .map_err(|_| Error::InvalidFragment(format!("invalid number: {}", s)))
+}
+
+fn parse_md5(s: &str) -> Result<[u8; 16], Error> {
+    if s.len() != 32 || !s.is_ascii() {
+        return Err(Error::InvalidFragment(format!("invalid md5 value: {}", s)));
+    }
+    let mut digest = [0; 16];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)
+            .map_err(|_| Error::InvalidFragment(format!("invalid md5 value: {}", s)))?;
+    }
+    Ok(digest)
+}
+
+/// Checks that a charset is one we can compute lengths and hashes for (the text is always UTF-8 on disk)
+fn check_charset(charset: &str) -> Result<(), Error> {
+    if charset.eq_ignore_ascii_case("utf-8") || charset.eq_ignore_ascii_case("us-ascii") {
+        Ok(())
+    } else {
+        Err(Error::InvalidFragment(format!(
+            "unsupported charset: {}",
+            charset
+        )))
+    }
+}
+
+impl TextFile {
+    /// Verifies the integrity checks of a fragment identifier (if any) and resolves it to absolute character offsets (begin, non-inclusive end).
+    /// Returns `Error::LengthMismatch` or `Error::Md5Mismatch` if an integrity check fails.
+    /// Resolving a `line=` fragment will return an `Error::NoLineIndex` if no line index was computed/loaded.
+    pub fn resolve_fragment(&self, fragment: &TextFragment) -> Result<(usize, usize), Error> {
+        self.check_integrity(fragment)?;
+        match fragment.scheme {
+            FragmentScheme::Char => {
+                let len = self.len();
+                let begin = fragment.begin.min(len);
+                let end = fragment.end.unwrap_or(len).min(len);
+                Ok((begin, end))
+            }
+            FragmentScheme::Line => {
+                if self.positionindex.lines.is_empty() {
+                    return Err(Error::NoLineIndex);
+                }
+                //the last entry is the end of the text
+                let last = self.positionindex.lines.len() - 1;
+                let begin = fragment.begin.min(last);
+                let end = fragment.end.unwrap_or(last).min(last);
+                Ok((
+                    self.bytes_to_chars(self.line_to_bytes(begin as isize)?)?,
+                    self.bytes_to_chars(self.line_to_bytes(end as isize)?)?,
+                ))
+            }
+        }
+    }
+
+    /// Returns the text a fragment identifier refers to, the fragment will be loaded from disk into memory if needed.
+    /// Returns `Error::LengthMismatch` or `Error::Md5Mismatch` if an integrity check fails.
+    pub fn get_or_load_fragment(&mut self, fragment: &TextFragment) -> Result<&str, Error> {
+        let (begin, end) = self.resolve_fragment(fragment)?;
+        self.get_or_load_abs(begin, end)
+    }
+
+    /// Checks the integrity checks of a fragment identifier against the text file
+    fn check_integrity(&self, fragment: &TextFragment) -> Result<(), Error> {
+        for check in fragment.integrity.iter() {
+            match check {
+                IntegrityCheck::Length { length, charset } => {
+                    let found = if let Some(charset) = charset {
+                        check_charset(charset)?;
+                        self.len_utf8()
+                    } else {
+                        self.len()
+                    };
+                    if *length != found {
+                        return Err(Error::LengthMismatch {
+                            expected: *length,
+                            found,
+                        });
+                    }
+                }
+                IntegrityCheck::Md5 { digest, charset } => {
+                    if let Some(charset) = charset {
+                        check_charset(charset)?;
+                    }
+                    let found = self.md5_digest()?;
+                    if *digest != found {
+                        return Err(Error::Md5Mismatch {
+                            expected: *digest,
+                            found,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Computes the MD5 hash of the text file (streaming from disk), as used in fragment identifiers.
+    pub fn md5_digest(&self) -> Result<[u8; 16], Error> {
+        let mut file = File::open(self.path.as_path()).map_err(Error::IOError)?;
+        let mut context = md5::Context::new();
+        let mut buffer = vec![0; 65536];
+        loop {
+            let n = file.read(&mut buffer).map_err(Error::IOError)?;
+            if n == 0 {
+                break;
+            }
+            context.consume(&buffer[..n]);
+        }
+        Ok(context.finalize().0)
+    }
+
+    /// Generates a fragment identifier for a range (absolute offsets, end non-inclusive) of characters or lines (0-indexed!!).
+    /// If `integrity` is set, the length (in characters) and MD5 hash of the text file are included.
+    pub fn to_fragment(
+        &self,
+        scheme: FragmentScheme,
+        begin: usize,
+        end: usize,
+        integrity: bool,
+    ) -> Result<TextFragment, Error> {
+        let max = match scheme {
+            FragmentScheme::Char => self.len(),
+            FragmentScheme::Line => {
+                if self.positionindex.lines.is_empty() {
+                    return Err(Error::NoLineIndex);
+                }
+                self.positionindex.lines.len() - 1
+            }
+        };
+        if end > max {
+            return Err(Error::OutOfBoundsError {
+                begin: begin as isize,
+                end: end as isize,
+            });
+        }
+        let mut fragment = TextFragment::new(scheme, begin, Some(end))?;
+        if integrity {
+            fragment = fragment
+                .with_integrity(IntegrityCheck::Length {
+                    length: self.len(),
+                    charset: None,
+                })
+                .with_integrity(IntegrityCheck::Md5 {
+                    digest: self.md5_digest()?,
+                    charset: None,
+                });
+        }
+        Ok(fragment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+
+    #[test]
+    pub fn test028_parse_fragment() {
+        let fragment = TextFragment::parse("#char=10,20").unwrap();
+        assert_eq!(fragment.scheme(), FragmentScheme::Char);
+        assert_eq!(fragment.begin(), 10);
+        assert_eq!(fragment.end(), Some(20));
+        let fragment: TextFragment = "line=3,".parse().unwrap();
+        assert_eq!(fragment.scheme(), FragmentScheme::Line);
+        assert_eq!(fragment.end(), None);
+        let fragment = TextFragment::parse("char=,5").unwrap();
+        assert_eq!((fragment.begin(), fragment.end()), (0, Some(5)));
+        let fragment = TextFragment::parse("line=7").unwrap();
+        assert_eq!((fragment.begin(), fragment.end()), (7, Some(7)));
+        let fragment =
+            TextFragment::parse("char=0,5;length=914,UTF-8;md5=d41d8cd98f00b204e9800998ecf8427e")
+                .unwrap();
+        assert_eq!(fragment.integrity().len(), 2);
+        assert_eq!(
+            fragment.to_string(),
+            "char=0,5;length=914,UTF-8;md5=d41d8cd98f00b204e9800998ecf8427e"
+        );
+        for invalid in [
+            "char=",
+            "char=,",
+            "char=a,5",
+            "char=-1,5",
+            "char=5,3",
+            "word=1",
+            "char=1;md5=xyz",
+            "char=1;foo=2",
+        ] {
+            assert!(
+                matches!(TextFragment::parse(invalid), Err(Error::InvalidFragment(_))),
+                "{} should not parse",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    pub fn test028_resolve_fragment() {
+        let file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let fragment = TextFragment::parse("char=1,10").unwrap();
+        assert_eq!(
+            textfile
+                .get_or_load_fragment(&fragment)
+                .expect("text should exist"),
+            "Article 1"
+        );
+        let fragment = TextFragment::parse("line=1,2").unwrap();
+        assert_eq!(
+            textfile
+                .get_or_load_fragment(&fragment)
+                .expect("text should exist"),
+            "Article 1\n"
+        );
+        //beyond the end refers to the end
+        let fragment = TextFragment::parse("char=900,").unwrap();
+        assert_eq!(textfile.resolve_fragment(&fragment).unwrap(), (900, 914));
+        let fragment = TextFragment::parse("line=1000").unwrap();
+        assert_eq!(textfile.resolve_fragment(&fragment).unwrap(), (914, 914));
+    }
+
+    #[test]
+    pub fn test028_fragment_integrity() {
+        let file = setup_unicode();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let fragment = textfile
+            .to_fragment(FragmentScheme::Char, 1, 4, true)
+            .unwrap();
+        assert_eq!(textfile.resolve_fragment(&fragment).unwrap(), (1, 4));
+        let reparsed = TextFragment::parse(&fragment.to_string()).unwrap();
+        assert_eq!(reparsed, fragment);
+        let fragment = TextFragment::parse("char=1,4;length=271").unwrap();
+        assert!(textfile.resolve_fragment(&fragment).is_ok());
+        let fragment = TextFragment::parse("char=1,4;length=771,utf-8").unwrap();
+        assert!(textfile.resolve_fragment(&fragment).is_ok());
+        let fragment = TextFragment::parse("char=1,4;length=771").unwrap();
+        assert!(matches!(
+            textfile.resolve_fragment(&fragment),
+            Err(Error::LengthMismatch {
+                expected: 771,
+                found: 271
+            })
+        ));
+        let fragment =
+            TextFragment::parse("char=1,4;md5=d41d8cd98f00b204e9800998ecf8427e").unwrap();
+        assert!(matches!(
+            textfile.resolve_fragment(&fragment),
+            Err(Error::Md5Mismatch { .. })
+        ));
+        let fragment = TextFragment::parse("char=1,4;length=271,ISO-8859-1").unwrap();
+        assert!(matches!(
+            textfile.resolve_fragment(&fragment),
+            Err(Error::InvalidFragment(_))
+        ));
+    }
+}
@@ -0,0 +1,456 @@
+/*
+TextFrame
+  by Maarten van Gompel <proycon@anaproy.nl>
+  Digital Infrastructure, KNAW Humanities Cluster
+  licensed under the GNU General Public Licence v3
+*/
+
+//! An optional full-text index (suffix array) for fast repeated substring queries, stored along with the position index
+
+use minicbor::{Decode, Encode};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::{Error, TextFile};
+
+#[derive(Debug, Clone, Decode, Encode)]
+/// Abstraction over differently sized suffix vectors
+enum Suffixes {
+    #[n(0)]
+    Large(#[n(0)] Vec<u32>),
+
+    #[n(1)]
+    Huge(#[n(0)] Vec<u64>),
+}
+
+#[derive(Debug, Clone, Decode, Encode)]
+/// A suffix array over the text: the character offsets of all suffixes of the text, in lexicographical order.
+/// The order of UTF-8 byte sequences equals that of the unicode points they encode, so the array can be searched with UTF-8 patterns directly.
+pub struct SuffixArray {
+    #[n(0)]
+    suffixes: Suffixes,
+}
+
+impl SuffixArray {
+    /// Builds a suffix array for a text file with the SA-IS algorithm, in time linear to the length of the text.
+    /// The entire text is read into memory once. For texts smaller than `u32::MAX` bytes, all working buffers hold 32-bit integers
+    /// and building takes about 20 bytes of memory per character at its peak (up to about 40 in the worst case), twice that for
+    /// larger texts. The resulting suffix array takes 4 (or 8) bytes per character, in memory and in the index file.
+    pub(crate) fn build(textfile: &Path) -> Result<Self, Error> {
+        let mut text = String::new();
+        File::open(textfile)
+            .map_err(Error::IOError)?
+            .read_to_string(&mut text)
+            .map_err(Error::IOError)?;
+        let suffixes = if text.len() < u32::MAX as usize {
+            //the number of characters is at most the number of bytes
+            Suffixes::Large(sa_is_text(text))
+        } else {
+            Suffixes::Huge(sa_is_text(text))
+        };
+        Ok(Self { suffixes })
+    }
+
+    /// Returns the number of suffixes (equal to the length of the text in characters)
+    pub fn len(&self) -> usize {
+        match &self.suffixes {
+            Suffixes::Large(v) => v.len(),
+            Suffixes::Huge(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the character offset of the suffix at the given rank
+    fn get(&self, index: usize) -> usize {
+        match &self.suffixes {
+            Suffixes::Large(v) => v[index] as usize,
+            Suffixes::Huge(v) => v[index] as usize,
+        }
+    }
+}
+
+/// Integer type for suffix array construction, the text must be shorter than its maximum value
+trait SuffixIndex: Copy + Default + Ord {
+    fn from_usize(value: usize) -> Self;
+    fn to_usize(self) -> usize;
+}
+
+impl SuffixIndex for u32 {
+    fn from_usize(value: usize) -> Self {
+        value as u32
+    }
+
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+impl SuffixIndex for u64 {
+    fn from_usize(value: usize) -> Self {
+        value as u64
+    }
+
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+/// Builds the suffix array (over characters) of a text, the text is consumed to limit memory usage
+fn sa_is_text<T: SuffixIndex>(text: String) -> Vec<T> {
+    // map the characters to a dense alphabet, keeping their order
+    let mut present = vec![false; char::MAX as usize + 1];
+    for c in text.chars() {
+        present[c as usize] = true;
+    }
+    let mut ranks: Vec<u32> = vec![0; present.len()];
+    let mut upper = 0;
+    for (c, rank) in ranks.iter_mut().enumerate() {
+        if present[c] {
+            *rank = upper;
+            upper += 1;
+        }
+    }
+    drop(present);
+    let s: Vec<T> = text
+        .chars()
+        .map(|c| T::from_usize(ranks[c as usize] as usize))
+        .collect();
+    drop(ranks);
+    drop(text);
+    sa_is(&s, (upper as usize).saturating_sub(1))
+}
+
+/// The SA-IS suffix array construction algorithm (Nong, Zhang & Chan, 2009) over a string of integers no greater than `upper`.
+/// While inducing, suffix array entries are offset by one so that 0 marks an empty slot.
+fn sa_is<T: SuffixIndex>(s: &[T], upper: usize) -> Vec<T> {
+    let n = s.len();
+    match n {
+        0 => return Vec::new(),
+        1 => return vec![T::from_usize(0)],
+        2 => {
+            return if s[0] < s[1] {
+                vec![T::from_usize(0), T::from_usize(1)]
+            } else {
+                vec![T::from_usize(1), T::from_usize(0)]
+            }
+        }
+        _ => {}
+    }
+    let c = |i: usize| s[i].to_usize();
+    // S-type (true) or L-type (false) suffixes
+    let mut ls = vec![false; n];
+    for i in (0..n - 1).rev() {
+        ls[i] = if s[i] == s[i + 1] {
+            ls[i + 1]
+        } else {
+            s[i] < s[i + 1]
+        };
+    }
+    // bucket boundaries: sum_l[x] is where the L-type suffixes starting with x begin, sum_s[x] where the S-type ones begin
+    let mut sum_l = vec![0usize; upper + 2];
+    let mut sum_s = vec![0usize; upper + 2];
+    for i in 0..n {
+        if ls[i] {
+            sum_l[c(i) + 1] += 1;
+        } else {
+            sum_s[c(i)] += 1;
+        }
+    }
+    for x in 0..=upper {
+        sum_s[x] += sum_l[x];
+        sum_l[x + 1] += sum_s[x];
+    }
+
+    let mut sa: Vec<T> = vec![T::default(); n];
+    let induce = |sa: &mut [T], lms: &[T]| {
+        sa.fill(T::default());
+        let mut buf = sum_s.clone();
+        for d in lms.iter().map(|d| d.to_usize()) {
+            if d == n {
+                continue;
+            }
+            sa[buf[c(d)]] = T::from_usize(d + 1);
+            buf[c(d)] += 1;
+        }
+        buf.copy_from_slice(&sum_l);
+        sa[buf[c(n - 1)]] = T::from_usize(n);
+        buf[c(n - 1)] += 1;
+        for i in 0..n {
+            let v = sa[i].to_usize();
+            if v >= 2 && !ls[v - 2] {
+                sa[buf[c(v - 2)]] = T::from_usize(v - 1);
+                buf[c(v - 2)] += 1;
+            }
+        }
+        buf.copy_from_slice(&sum_l);
+        for i in (0..n).rev() {
+            let v = sa[i].to_usize();
+            if v >= 2 && ls[v - 2] {
+                buf[c(v - 2) + 1] -= 1;
+                sa[buf[c(v - 2) + 1]] = T::from_usize(v - 1);
+            }
+        }
+    };
+
+    // leftmost S-type positions, numbered from 1 in lms_map
+    let mut lms_map: Vec<T> = vec![T::default(); n + 1];
+    let mut lms: Vec<T> = Vec::new();
+    for i in 1..n {
+        if !ls[i - 1] && ls[i] {
+            lms.push(T::from_usize(i));
+            lms_map[i] = T::from_usize(lms.len());
+        }
+    }
+    let m = lms.len();
+    induce(&mut sa, &lms);
+
+    if m > 0 {
+        // name the sorted LMS substrings and sort them recursively
+        let mut sorted_lms: Vec<T> = Vec::with_capacity(m);
+        for v in sa.iter() {
+            let v = v.to_usize() - 1;
+            if lms_map[v] != T::default() {
+                sorted_lms.push(T::from_usize(v));
+            }
+        }
+        let mut rec_s: Vec<T> = vec![T::default(); m];
+        let mut rec_upper = 0;
+        for i in 1..m {
+            let mut l = sorted_lms[i - 1].to_usize();
+            let mut r = sorted_lms[i].to_usize();
+            let end = |x: usize| match lms_map[x].to_usize() {
+                next if next < m => lms[next].to_usize(),
+                _ => n,
+            };
+            let (end_l, end_r) = (end(l), end(r));
+            let same = if end_l - l != end_r - r {
+                false
+            } else {
+                while l < end_l && s[l] == s[r] {
+                    l += 1;
+                    r += 1;
+                }
+                l != n && s[l] == s[r]
+            };
+            if !same {
+                rec_upper += 1;
+            }
+            rec_s[lms_map[sorted_lms[i].to_usize()].to_usize() - 1] = T::from_usize(rec_upper);
+        }
+        let rec_sa = sa_is(&rec_s, rec_upper);
+        drop(rec_s);
+        for (sorted, rank) in sorted_lms.iter_mut().zip(rec_sa) {
+            *sorted = lms[rank.to_usize()];
+        }
+        induce(&mut sa, &sorted_lms);
+    }
+    for v in sa.iter_mut() {
+        *v = T::from_usize(v.to_usize() - 1);
+    }
+    sa
+}
+
+impl TextFile {
+    /// Returns the full-text index (if enabled, see `TextFileOptions::with_fulltext()`)
+    fn fulltext(&self) -> Result<&SuffixArray, Error> {
+        self.positionindex
+            .fulltext
+            .as_ref()
+            .ok_or(Error::NoFullTextIndex)
+    }
+
+    /// Returns the number of (possibly overlapping) occurrences of a pattern in the text, using the full-text index.
+    /// Only a handful of reads from disk are needed, the text is not loaded into memory.
+    /// This will return an `Error::NoFullTextIndex` if no full-text index was computed (see `TextFileOptions::with_fulltext()`).
+    pub fn fulltext_count(&self, pattern: &str) -> Result<usize, Error> {
+        let (lower, upper) = self.fulltext_bounds(pattern)?;
+        Ok(upper - lower)
+    }
+
+    /// Returns all (possibly overlapping) occurrences of a pattern in the text as character ranges (absolute offsets, end non-inclusive),
+    /// in order, using the full-text index. The text is not loaded into memory.
+    /// This will return an `Error::NoFullTextIndex` if no full-text index was computed (see `TextFileOptions::with_fulltext()`).
+    pub fn fulltext_locate(&self, pattern: &str) -> Result<Vec<(usize, usize)>, Error> {
+        let (lower, upper) = self.fulltext_bounds(pattern)?;
+        let suffixarray = self.fulltext()?;
+        let length = pattern.chars().count();
+        let mut results: Vec<(usize, usize)> = (lower..upper)
+            .map(|index| {
+                let begin = suffixarray.get(index);
+                (begin, begin + length)
+            })
+            .collect();
+        results.sort_unstable();
+        Ok(results)
+    }
+
+    /// Returns the range of ranks in the suffix array of all suffixes that start with the pattern
+    fn fulltext_bounds(&self, pattern: &str) -> Result<(usize, usize), Error> {
+        let suffixarray = self.fulltext()?;
+        #[cfg(feature = "watch")]
+        self.check_watched_pending()?;
+        if pattern.is_empty() {
+            return Err(Error::InvalidPattern("pattern is empty".to_string()));
+        }
+        let pattern = pattern.as_bytes();
+        let mut file = File::open(self.path.as_path()).map_err(Error::IOError)?;
+        let mut buffer: Vec<u8> = Vec::with_capacity(pattern.len());
+        // first rank for which the suffix is not smaller than the pattern (lower), or greater than it (upper)
+        let mut bounds = [0, 0];
+        for (bound, inclusive) in bounds.iter_mut().zip([false, true]) {
+            let mut low = 0;
+            let mut high = suffixarray.len();
+            while low < high {
+                let mid = (low + high) / 2;
+                self.read_prefix(&mut file, suffixarray.get(mid), pattern.len(), &mut buffer)?;
+                let before = if inclusive {
+                    buffer.as_slice() <= pattern
+                } else {
+                    buffer.as_slice() < pattern
+                };
+                if before {
+                    low = mid + 1;
+                } else {
+                    high = mid;
+                }
+            }
+            *bound = low;
+        }
+        Ok((bounds[0], bounds[1]))
+    }
+
+    /// Reads up to `length` bytes from disk, starting at the given character position
+    fn read_prefix(
+        &self,
+        file: &mut File,
+        charpos: usize,
+        length: usize,
+        buffer: &mut Vec<u8>,
+    ) -> Result<(), Error> {
+        let bytepos = self.chars_to_bytes(charpos)?;
+        let length = length.min(self.positionindex.bytesize - bytepos);
+        buffer.clear();
+        file.seek(SeekFrom::Start(bytepos as u64))
+            .map_err(Error::IOError)?;
+        file.take(length as u64)
+            .read_to_end(buffer)
+            .map_err(Error::IOError)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use crate::{TextFileMode, TextFileOptions};
+
+    fn fulltext_options() -> TextFileOptions {
+        TextFileOptions::new(TextFileMode::default()).with_fulltext(true)
+    }
+
+    #[test]
+    pub fn test034_fulltext_ascii() {
+        let file = setup_ascii();
+        let textfile =
+            TextFile::with_options(file.path(), None, fulltext_options()).expect("file must load");
+        for pattern in ["Article", "e", "brotherhood", "\n\n", "rights", "xyz", "}"] {
+            let expected: Vec<(usize, usize)> = textfile
+                .find_iter(pattern)
+                .map(|m| m.expect("match"))
+                .collect();
+            assert_eq!(
+                textfile.fulltext_locate(pattern).unwrap(),
+                expected,
+                "{}",
+                pattern
+            );
+            assert_eq!(textfile.fulltext_count(pattern).unwrap(), expected.len());
+        }
+        assert_eq!(textfile.fulltext_count("Article").unwrap(), 4);
+        //overlapping occurrences
+        assert_eq!(
+            textfile.fulltext_count("\n").unwrap(),
+            EXAMPLE_ASCII_TEXT.matches('\n').count()
+        );
+        assert!(textfile.fulltext_count("").is_err());
+    }
+
+    #[test]
+    pub fn test034_fulltext_unicode() {
+        let file = setup_unicode();
+        let mut textfile =
+            TextFile::with_options(file.path(), None, fulltext_options()).expect("file must load");
+        let found = textfile.fulltext_locate("条").unwrap();
+        assert!(!found.is_empty());
+        assert_eq!(found[0], (3, 4));
+        for (begin, end) in found {
+            assert_eq!(
+                textfile
+                    .get_or_load(begin as isize, end as isize)
+                    .expect("text should exist"),
+                "条"
+            );
+        }
+    }
+
+    #[test]
+    pub fn test034_fulltext_cached() {
+        let file = setup_ascii();
+        let indexfile = tempfile::NamedTempFile::new().expect("temp file");
+        std::fs::remove_file(indexfile.path()).expect("remove");
+        let textfile = TextFile::new(file.path(), Some(indexfile.path()), Default::default())
+            .expect("file must load");
+        assert!(matches!(
+            textfile.fulltext_count("Article"),
+            Err(Error::NoFullTextIndex)
+        ));
+        //added to the existing cached index
+        let textfile =
+            TextFile::with_options(file.path(), Some(indexfile.path()), fulltext_options())
+                .expect("file must load");
+        assert_eq!(textfile.fulltext_count("Article").unwrap(), 4);
+        //loaded from the cached index
+        let textfile = TextFile::new(file.path(), Some(indexfile.path()), Default::default())
+            .expect("file must load");
+        assert_eq!(textfile.fulltext_count("Article").unwrap(), 4);
+    }
+
+    #[test]
+    pub fn test034_sa_is() {
+        //compare against naive sorting for many small (pseudo-random) strings over small alphabets
+        let mut seed: u64 = 42;
+        for length in 0..200 {
+            for upper in [0, 1, 2, 3, 25] {
+                let s: Vec<u32> = (0..length)
+                    .map(|_| {
+                        seed = seed
+                            .wrapping_mul(6364136223846793005)
+                            .wrapping_add(1442695040888963407);
+                        ((seed >> 33) % (upper + 1)) as u32
+                    })
+                    .collect();
+                let mut expected: Vec<u32> = (0..length as u32).collect();
+                expected.sort_by_key(|i| &s[*i as usize..]);
+                assert_eq!(sa_is(&s, upper as usize), expected, "{:?}", s);
+                let s: Vec<u64> = s.into_iter().map(|x| x as u64).collect();
+                let expected: Vec<u64> = expected.into_iter().map(|x| x as u64).collect();
+                assert_eq!(sa_is(&s, upper as usize), expected);
+            }
+        }
+        //characters are ordered by their unicode points
+        assert_eq!(sa_is_text::<u32>("банан".to_string()), vec![3, 1, 0, 4, 2]);
+    }
+
+    #[test]
+    pub fn test034_fulltext_empty() {
+        let file = setup_empty();
+        let textfile =
+            TextFile::with_options(file.path(), None, fulltext_options()).expect("file must load");
+        assert_eq!(textfile.fulltext_count("a").unwrap(), 0);
+    }
+}
@@ -0,0 +1,304 @@
+/*
+TextFrame
+  by Maarten van Gompel <proycon@anaproy.nl>
+  Digital Infrastructure, KNAW Humanities Cluster
+  licensed under the GNU General Public Licence v3
+*/
+
+//! Approximate (fuzzy) search with a bounded edit distance, using Myers' bit-parallel algorithm, streaming through the text file
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::search::{ChunkReader, CHUNKSIZE};
+use crate::{Error, TextFile};
+
+/// Maximum length of a fuzzy search pattern in characters
+pub const MAX_FUZZY_PATTERN: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// An approximate match, offsets are absolute character offsets (end non-inclusive)
+pub struct FuzzyMatch {
+    pub begin: usize,
+    pub end: usize,
+
+    /// The edit (Levenshtein) distance between the pattern and the matched text
+    pub distance: usize,
+}
+
+/// Iterator over approximate matches of a pattern in a text file, see `TextFile::fuzzy_iter()`
+pub struct FuzzyIter<'a> {
+    textfile: &'a TextFile,
+    pattern: Vec<char>,
+    max_distance: usize,
+
+    /// For each character in the pattern, the bitmask of the positions it occurs at
+    peq: HashMap<char, u64>,
+
+    /// Bit-vectors of positive and negative vertical deltas
+    pv: u64,
+    mv: u64,
+
+    /// Edit distance of the best match ending at the current position
+    score: usize,
+
+    /// The last characters read, enough to hold any match
+    window: VecDeque<char>,
+
+    /// Absolute character offset of the next character to process
+    charpos: usize,
+
+    /// The best match in the current run of decreasing distances, not yet returned
+    pending: Option<FuzzyMatch>,
+
+    /// Initialised on the first call to `next()`
+    reader: Option<ChunkReader>,
+
+    /// Matches found but not returned yet
+    queue: VecDeque<FuzzyMatch>,
+
+    done: bool,
+}
+
+impl FuzzyIter<'_> {
+    /// Processes a single character of the text (one column of the dynamic programming matrix)
+    fn step(&mut self, c: char) {
+        let m = self.pattern.len();
+        let mask = if m == 64 { u64::MAX } else { (1 << m) - 1 };
+        let last = 1 << (m - 1);
+        let eq = self.peq.get(&c).copied().unwrap_or(0);
+        let xv = eq | self.mv;
+        let xh = ((eq & self.pv).wrapping_add(self.pv) ^ self.pv) | eq;
+        let mut ph = self.mv | !(xh | self.pv);
+        let mut mh = self.pv & xh;
+        let previous = self.score;
+        if ph & last != 0 {
+            self.score += 1;
+        } else if mh & last != 0 {
+            self.score -= 1;
+        }
+        // the first row is all zeros when searching, a match may begin anywhere
+        ph <<= 1;
+        mh <<= 1;
+        self.pv = (mh | !(xv | ph)) & mask;
+        self.mv = ph & xv & mask;
+
+        self.window.push_back(c);
+        if self.window.len() > m + self.max_distance {
+            self.window.pop_front();
+        }
+        self.charpos += 1;
+
+        // report one match per local minimum of the distance
+        if let Some(pending) = self.pending {
+            if self.score < pending.distance {
+                self.pending = Some(self.to_match());
+            } else if self.score > pending.distance {
+                self.queue.push_back(pending);
+                self.pending = None;
+            }
+        } else if self.score <= self.max_distance && self.score < previous {
+            self.pending = Some(self.to_match());
+        }
+    }
+
+    /// Returns the match ending at the current position, finds where it begins by aligning the pattern backwards against the window
+    fn to_match(&self) -> FuzzyMatch {
+        let m = self.pattern.len();
+        // row[l]: distance between the last i pattern characters and the last l window characters
+        let mut row: Vec<usize> = (0..=self.window.len()).collect();
+        for i in 1..=m {
+            let pc = self.pattern[m - i];
+            let mut diagonal = row[0];
+            row[0] = i;
+            for l in 1..row.len() {
+                let tc = self.window[self.window.len() - l];
+                let value = (diagonal + (pc != tc) as usize)
+                    .min(row[l] + 1)
+                    .min(row[l - 1] + 1);
+                diagonal = row[l];
+                row[l] = value;
+            }
+        }
+        //the shortest match with the minimal distance
+        let (length, distance) = row
+            .iter()
+            .enumerate()
+            .min_by_key(|(l, d)| (**d, *l))
+            .map(|(l, d)| (l, *d))
+            .expect("row is not empty");
+        FuzzyMatch {
+            begin: self.charpos - length,
+            end: self.charpos,
+            distance,
+        }
+    }
+
+    /// Reads and processes the next chunk
+    fn search_next_chunk(&mut self) -> Result<(), Error> {
+        if self.reader.is_none() {
+            self.reader = Some(self.textfile.chunk_reader(0, CHUNKSIZE)?);
+        }
+        let reader = self.reader.as_mut().expect("reader initialised");
+        reader.read_chunk()?;
+        let text = reader.buffer().to_string();
+        reader.discard(text.len());
+        let done = reader.is_done();
+        for c in text.chars() {
+            self.step(c);
+        }
+        if done {
+            if let Some(pending) = self.pending.take() {
+                self.queue.push_back(pending);
+            }
+            self.done = true;
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for FuzzyIter<'_> {
+    type Item = Result<FuzzyMatch, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(m) = self.queue.pop_front() {
+                return Some(Ok(m));
+            }
+            if self.done {
+                return None;
+            }
+            if let Err(e) = self.search_next_chunk() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+impl TextFile {
+    /// Returns an iterator over approximate matches of a pattern, with an edit (Levenshtein) distance of at most `max_distance`
+    /// (counted in characters). The text file is streamed through in bounded chunks rather than loaded into memory.
+    /// A single match is returned for each place where the distance reaches a local minimum, in order of their end offset.
+    /// A distance can never exceed the length of the pattern, so a larger `max_distance` is clamped to that.
+    ///
+    /// The pattern may be at most `MAX_FUZZY_PATTERN` characters long, an `Error::InvalidPattern` is returned otherwise.
+    pub fn fuzzy_iter(&self, pattern: &str, max_distance: usize) -> Result<FuzzyIter<'_>, Error> {
+        let pattern: Vec<char> = pattern.chars().collect();
+        if pattern.is_empty() || pattern.len() > MAX_FUZZY_PATTERN {
+            return Err(Error::InvalidPattern(format!(
+                "pattern must be between 1 and {} characters long",
+                MAX_FUZZY_PATTERN
+            )));
+        }
+        let mut peq: HashMap<char, u64> = HashMap::new();
+        for (i, c) in pattern.iter().enumerate() {
+            *peq.entry(*c).or_default() |= 1 << i;
+        }
+        let m = pattern.len();
+        Ok(FuzzyIter {
+            textfile: self,
+            pattern,
+            max_distance: max_distance.min(m),
+            peq,
+            pv: if m == 64 { u64::MAX } else { (1 << m) - 1 },
+            mv: 0,
+            score: m,
+            window: VecDeque::new(),
+            charpos: 0,
+            pending: None,
+            reader: None,
+            queue: VecDeque::new(),
+            done: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+
+    fn fuzzy(textfile: &TextFile, pattern: &str, max_distance: usize) -> Vec<(String, usize)> {
+        textfile
+            .fuzzy_iter(pattern, max_distance)
+            .expect("pattern must be valid")
+            .map(|m| m.expect("match"))
+            .map(|m| (EXAMPLE_ASCII_TEXT[m.begin..m.end].to_string(), m.distance))
+            .collect()
+    }
+
+    #[test]
+    pub fn test033_fuzzy_exact() {
+        let file = setup_ascii();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        assert_eq!(
+            fuzzy(&textfile, "brotherhood", 0),
+            vec![("brotherhood".to_string(), 0)]
+        );
+        assert_eq!(fuzzy(&textfile, "Article", 0).len(), 4);
+    }
+
+    #[test]
+    pub fn test033_fuzzy_ocr_errors() {
+        let file = setup_ascii();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        //substitution
+        assert_eq!(
+            fuzzy(&textfile, "br0therhood", 1),
+            vec![("brotherhood".to_string(), 1)]
+        );
+        //insertion and deletion
+        assert_eq!(
+            fuzzy(&textfile, "brothrhoood", 2),
+            vec![("brotherhood".to_string(), 2)]
+        );
+        assert!(fuzzy(&textfile, "br0thrh0ood", 2).is_empty());
+        let found = fuzzy(&textfile, "Artlcle", 1);
+        assert_eq!(found.len(), 4);
+        assert!(found.iter().all(|(text, d)| text == "Article" && *d == 1));
+    }
+
+    #[test]
+    pub fn test033_fuzzy_max_distance_clamped() {
+        let file = setup_ascii();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let expected: Vec<FuzzyMatch> = textfile
+            .fuzzy_iter("cd", 2)
+            .unwrap()
+            .map(|m| m.expect("match"))
+            .collect();
+        let found: Vec<FuzzyMatch> = textfile
+            .fuzzy_iter("cd", usize::MAX)
+            .unwrap()
+            .map(|m| m.expect("match"))
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    pub fn test033_fuzzy_unicode_and_invalid() {
+        let file = setup_unicode();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let found: Vec<FuzzyMatch> = textfile
+            .fuzzy_iter("第二条", 1)
+            .unwrap()
+            .map(|m| m.expect("match"))
+            .collect();
+        assert!(!found.is_empty());
+        let m = found[0];
+        assert_eq!((m.begin, m.end, m.distance), (1, 4, 1));
+        assert_eq!(
+            textfile
+                .get_or_load(m.begin as isize, m.end as isize)
+                .expect("text should exist"),
+            "第一条"
+        );
+        assert!(textfile.fuzzy_iter("", 1).is_err());
+        assert!(textfile.fuzzy_iter(&"x".repeat(65), 1).is_err());
+        assert!(textfile.fuzzy_iter(&"x".repeat(64), 1).is_ok());
+    }
+}
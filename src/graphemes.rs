@@ -0,0 +1,409 @@
+/*
+TextFrame
+  by Maarten van Gompel <proycon@anaproy.nl>
+  Digital Infrastructure, KNAW Humanities Cluster
+  licensed under the GNU General Public Licence v3
+*/
+
+//! Support for offsets in extended grapheme clusters (user-perceived characters)
+
+use minicbor::{Decode, Encode};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{Error, TextFile};
+
+#[derive(Debug, Clone, Decode, Encode)]
+/// A grapheme cluster that consists of multiple unicode characters
+pub(crate) struct GraphemeData {
+    /// Grapheme cluster offset
+    #[n(0)]
+    graphemepos: u64,
+
+    /// Unicode point where the cluster begins
+    #[n(1)]
+    charpos: u64,
+
+    /// Length of the cluster in unicode points (always > 1)
+    #[n(2)]
+    length: u32,
+}
+
+#[derive(Debug, Clone, Default, Decode, Encode)]
+/// Maps grapheme cluster offsets to character offsets.
+/// Only clusters that consist of multiple unicode characters are stored, all other clusters are single characters and their offsets can be computed.
+/// As such clusters are relatively rare in most texts, a single width is used rather than differently sized vectors.
+pub struct Graphemes {
+    /// Multi-character grapheme clusters, in order
+    #[n(0)]
+    clusters: Vec<GraphemeData>,
+
+    /// Total number of grapheme clusters
+    #[n(1)]
+    count: usize,
+}
+
+impl Graphemes {
+    /// Adds all grapheme clusters in a piece of text (e.g. a line) that starts at the given character position.
+    /// Grapheme clusters never span across a newline (except for CR LF, which a line contains in full), so lines can be processed independently.
+    pub(crate) fn update(&mut self, text: &str, mut charpos: usize) {
+        for grapheme in text.graphemes(true) {
+            let length = grapheme.chars().count();
+            if length > 1 {
+                self.clusters.push(GraphemeData {
+                    graphemepos: self.count as u64,
+                    charpos: charpos as u64,
+                    length: length as u32,
+                });
+            }
+            charpos += length;
+            self.count += 1;
+        }
+    }
+
+    /// Removes the last grapheme cluster (of the given length in characters), so it can be segmented again along with text that is appended
+    pub(crate) fn remove_last(&mut self, length: usize) {
+        if self.count == 0 {
+            return;
+        }
+        self.count -= 1;
+        if length > 1 {
+            self.clusters.pop();
+        }
+    }
+
+    /// Returns the total number of grapheme clusters
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Returns the number of grapheme clusters that consist of multiple unicode characters
+    pub fn multichar_len(&self) -> usize {
+        self.clusters.len()
+    }
+
+    /// Converts a grapheme cluster offset to a character offset, returns None if out of bounds
+    fn to_chars(&self, graphemepos: usize) -> Option<usize> {
+        if graphemepos > self.count {
+            return None;
+        }
+        match self
+            .clusters
+            .binary_search_by_key(&graphemepos, |cluster| cluster.graphemepos as usize)
+        {
+            Ok(index) => Some(self.clusters[index].charpos as usize),
+            Err(0) => Some(graphemepos),
+            Err(index) => {
+                let cluster = &self.clusters[index - 1];
+                Some(
+                    cluster.charpos as usize
+                        + cluster.length as usize
+                        + (graphemepos - cluster.graphemepos as usize - 1),
+                )
+            }
+        }
+    }
+
+    /// Converts a character offset to a grapheme cluster offset.
+    /// Returns the offset of the cluster the character is in, and the character offset where that cluster begins.
+    fn to_graphemes(&self, charpos: usize) -> (usize, usize) {
+        match self
+            .clusters
+            .binary_search_by_key(&charpos, |cluster| cluster.charpos as usize)
+        {
+            Ok(index) => (self.clusters[index].graphemepos as usize, charpos),
+            Err(0) => (charpos, charpos),
+            Err(index) => {
+                let cluster = &self.clusters[index - 1];
+                let clusterend = cluster.charpos as usize + cluster.length as usize;
+                if charpos < clusterend {
+                    //inside the cluster
+                    (cluster.graphemepos as usize, cluster.charpos as usize)
+                } else {
+                    (
+                        cluster.graphemepos as usize + 1 + (charpos - clusterend),
+                        charpos,
+                    )
+                }
+            }
+        }
+    }
+
+    /// Returns the length in characters of the grapheme cluster at the given grapheme offset
+    fn cluster_length(&self, graphemepos: usize) -> usize {
+        match self
+            .clusters
+            .binary_search_by_key(&graphemepos, |cluster| cluster.graphemepos as usize)
+        {
+            Ok(index) => self.clusters[index].length as usize,
+            Err(_) => 1,
+        }
+    }
+}
+
+impl TextFile {
+    /// Returns the grapheme index (if enabled, see `TextFileOptions::with_graphemes()`)
+    fn graphemes(&self) -> Result<&Graphemes, Error> {
+        self.positionindex
+            .graphemes
+            .as_ref()
+            .ok_or(Error::NoGraphemeIndex)
+    }
+
+    /// Returns the length of the total text file in extended grapheme clusters (user-perceived characters).
+    /// This will return an `Error::NoGraphemeIndex` if no grapheme index was computed (see `TextFileOptions::with_graphemes()`).
+    pub fn len_graphemes(&self) -> Result<usize, Error> {
+        Ok(self.graphemes()?.len())
+    }
+
+    /// Reads the last grapheme cluster of the text from disk, returns an empty string if no grapheme index was computed or the text is empty.
+    pub(crate) fn last_grapheme(&self) -> Result<String, Error> {
+        let Some(graphemes) = self.positionindex.graphemes.as_ref() else {
+            return Ok(String::new());
+        };
+        if graphemes.is_empty() {
+            return Ok(String::new());
+        }
+        let charpos = graphemes
+            .to_chars(graphemes.len() - 1)
+            .expect("cluster should exist");
+        let beginbyte = self.chars_to_bytes(charpos)?;
+        let mut buffer: Vec<u8> = vec![0; self.positionindex.bytesize - beginbyte];
+        let mut file = File::open(self.path.as_path()).map_err(Error::IOError)?;
+        file.seek(SeekFrom::Start(beginbyte as u64))
+            .map_err(Error::IOError)?;
+        file.read_exact(&mut buffer).map_err(Error::IOError)?;
+        String::from_utf8(buffer).map_err(Error::Utf8Error)
+    }
+
+    /// Convert a grapheme cluster offset to a character position.
+    /// This will return an `Error::NoGraphemeIndex` if no grapheme index was computed (see `TextFileOptions::with_graphemes()`).
+    pub fn graphemes_to_chars(&self, graphemepos: usize) -> Result<usize, Error> {
+        self.graphemes()?
+            .to_chars(graphemepos)
+            .ok_or(Error::OutOfBoundsError {
+                begin: graphemepos as isize,
+                end: 0,
+            })
+    }
+
+    /// Convert a character position to a grapheme cluster offset. Returns `Error::InvalidGraphemeBoundary` if the character is not at the start of a grapheme cluster.
+    /// This will return an `Error::NoGraphemeIndex` if no grapheme index was computed (see `TextFileOptions::with_graphemes()`).
+    pub fn chars_to_graphemes(&self, charpos: usize) -> Result<usize, Error> {
+        let graphemes = self.graphemes()?;
+        if charpos > self.positionindex.charsize {
+            return Err(Error::OutOfBoundsError {
+                begin: charpos as isize,
+                end: 0,
+            });
+        }
+        match graphemes.to_graphemes(charpos) {
+            (graphemepos, clusterbegin) if clusterbegin == charpos => Ok(graphemepos),
+            _ => Err(Error::InvalidGraphemeBoundary(charpos)),
+        }
+    }
+
+    /// Expands a character range (absolute offsets, end non-inclusive) outward so that it begins and ends at grapheme cluster boundaries.
+    /// This will return an `Error::NoGraphemeIndex` if no grapheme index was computed (see `TextFileOptions::with_graphemes()`).
+    pub fn snap_to_graphemes(&self, begin: usize, end: usize) -> Result<(usize, usize), Error> {
+        let graphemes = self.graphemes()?;
+        self.check_order(begin, end)?;
+        if end > self.positionindex.charsize {
+            return Err(Error::OutOfBoundsError {
+                begin: begin as isize,
+                end: end as isize,
+            });
+        }
+        let (_, newbegin) = graphemes.to_graphemes(begin);
+        let (graphemepos, clusterbegin) = graphemes.to_graphemes(end);
+        let newend = if clusterbegin == end {
+            end
+        } else {
+            clusterbegin + graphemes.cluster_length(graphemepos)
+        };
+        Ok((newbegin, newend))
+    }
+
+    /// Converts relative grapheme cluster offsets to absolute ones, in the same way as `absolute_pos()` does for character offsets.
+    ///
+    /// * `begin` - The begin offset in grapheme clusters (0-indexed). If negative, it is interpreted relative to the end of the text.
+    /// * `end` - The end offset in grapheme clusters (0-indexed, non-inclusive). If 0 or negative, it is interpreted relative to the end of the text.
+    pub fn absolute_grapheme_pos(
+        &self,
+        mut begin: isize,
+        mut end: isize,
+    ) -> Result<(usize, usize), Error> {
+        let len = self.len_graphemes()? as isize;
+        if begin < 0 {
+            begin += len;
+        }
+
+        if end <= 0 {
+            end += len;
+        }
+
+        if begin < 0 || end < 0 || begin > end {
+            return Err(Error::OutOfBoundsError { begin, end });
+        }
+
+        Ok((begin as usize, end as usize))
+    }
+
+    /// Returns a text fragment by grapheme cluster offsets. The fragment must already be in memory or an Error::NotLoaded will be returned.
+    /// Use `get_or_load_graphemes()` instead if the fragment might not be loaded yet.
+    ///
+    /// * `begin` - The begin offset in grapheme clusters (0-indexed). If negative, it is interpreted relative to the end of the text.
+    /// * `end` - The end offset in grapheme clusters (0-indexed, non-inclusive). If 0 or negative, it is interpreted relative to the end of the text.
+    pub fn get_graphemes(&self, begin: isize, end: isize) -> Result<&str, Error> {
+        let (begin, end) = self.absolute_grapheme_pos(begin, end)?;
+        self.get_abs(
+            self.graphemes_to_chars(begin)?,
+            self.graphemes_to_chars(end)?,
+        )
+    }
+
+    /// Returns a text fragment by grapheme cluster offsets, the fragment will be loaded from disk into memory if needed.
+    /// Use `get_graphemes()` instead if you are already sure the fragment is loaded.
+    ///
+    /// * `begin` - The begin offset in grapheme clusters (0-indexed). If negative, it is interpreted relative to the end of the text.
+    /// * `end` - The end offset in grapheme clusters (0-indexed, non-inclusive). If 0 or negative, it is interpreted relative to the end of the text.
+    pub fn get_or_load_graphemes(&mut self, begin: isize, end: isize) -> Result<&str, Error> {
+        let (begin, end) = self.absolute_grapheme_pos(begin, end)?;
+        let beginchar = self.graphemes_to_chars(begin)?;
+        let endchar = self.graphemes_to_chars(end)?;
+        self.get_or_load_abs(beginchar, endchar)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TextFileOptions;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    // contains a ZWJ emoji sequence (5 characters), a flag (2 characters), a combining diacritic (2 characters) and CR LF (2 characters)
+    const EXAMPLE_GRAPHEME_TEXT: &str = "Family: 👩‍👩‍👧!\r\nFlag: 🇳🇱, cafe\u{301}\n";
+
+    fn setup_graphemes() -> NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().expect("temp file");
+        write!(file, "{}", EXAMPLE_GRAPHEME_TEXT).expect("write must work");
+        file
+    }
+
+    fn load(file: &NamedTempFile) -> TextFile {
+        TextFile::with_options(
+            file.path(),
+            None,
+            TextFileOptions::default().with_graphemes(true),
+        )
+        .expect("file must load")
+    }
+
+    #[test]
+    pub fn test022_graphemes() {
+        let file = setup_graphemes();
+        let textfile = load(&file);
+        let graphemes: Vec<&str> = EXAMPLE_GRAPHEME_TEXT.graphemes(true).collect();
+        assert_eq!(textfile.len_graphemes().unwrap(), graphemes.len());
+        let mut charpos = 0;
+        for (graphemepos, grapheme) in graphemes.iter().enumerate() {
+            assert_eq!(textfile.graphemes_to_chars(graphemepos).unwrap(), charpos);
+            assert_eq!(textfile.chars_to_graphemes(charpos).unwrap(), graphemepos);
+            charpos += grapheme.chars().count();
+        }
+        assert_eq!(
+            textfile.graphemes_to_chars(graphemes.len()).unwrap(),
+            textfile.len()
+        );
+        //inside the ZWJ sequence
+        assert!(matches!(
+            textfile.chars_to_graphemes(9),
+            Err(Error::InvalidGraphemeBoundary(9))
+        ));
+    }
+
+    #[test]
+    pub fn test022_get_or_load_graphemes() {
+        let file = setup_graphemes();
+        let mut textfile = load(&file);
+        assert_eq!(
+            textfile
+                .get_or_load_graphemes(8, 10)
+                .expect("text should exist"),
+            "👩‍👩‍👧!"
+        );
+        assert_eq!(
+            textfile
+                .get_or_load_graphemes(-5, -1)
+                .expect("text should exist"),
+            "cafe\u{301}"
+        );
+        assert_eq!(
+            textfile.get_graphemes(8, 9).expect("text should exist"),
+            "👩‍👩‍👧"
+        );
+    }
+
+    #[test]
+    pub fn test022_snap_to_graphemes() {
+        let file = setup_graphemes();
+        let textfile = load(&file);
+        // the ZWJ sequence spans characters 8-13
+        assert_eq!(textfile.snap_to_graphemes(9, 11).unwrap(), (8, 13));
+        assert_eq!(textfile.snap_to_graphemes(8, 13).unwrap(), (8, 13));
+        assert_eq!(textfile.snap_to_graphemes(0, 8).unwrap(), (0, 8));
+    }
+
+    #[test]
+    pub fn test022_graphemes_refresh_appended() {
+        // each append continues the grapheme cluster before it: a combining diacritic, the second half of a flag, a ZWJ sequence and CR LF
+        let parts = ["cafe", "\u{301} 🇳", "🇱 👩", "\u{200d}👧 x\r", "\nend"];
+        let mut file = tempfile::NamedTempFile::new().expect("temp file");
+        write!(file, "{}", parts[0]).expect("write must work");
+        let mut textfile = load(&file);
+        let mut text = parts[0].to_string();
+        for part in &parts[1..] {
+            write!(file, "{}", part).expect("write must work");
+            text += part;
+            textfile.refresh_appended().expect("refresh must work");
+            let textfile2 = load(&file);
+            let graphemes: Vec<&str> = text.graphemes(true).collect();
+            assert_eq!(
+                textfile.len_graphemes().unwrap(),
+                graphemes.len(),
+                "{}",
+                text
+            );
+            assert_eq!(textfile2.len_graphemes().unwrap(), graphemes.len());
+            for graphemepos in 0..=graphemes.len() {
+                assert_eq!(
+                    textfile.graphemes_to_chars(graphemepos).unwrap(),
+                    textfile2.graphemes_to_chars(graphemepos).unwrap()
+                );
+            }
+        }
+        assert_eq!(
+            textfile
+                .get_or_load_graphemes(3, 4)
+                .expect("text should exist"),
+            "e\u{301}"
+        );
+    }
+
+    #[test]
+    pub fn test022_no_grapheme_index() {
+        let file = setup_graphemes();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        assert!(matches!(
+            textfile.len_graphemes(),
+            Err(Error::NoGraphemeIndex)
+        ));
+    }
+}
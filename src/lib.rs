@@ -20,12 +20,51 @@ use std::path::{Path, PathBuf};
 use std::string::FromUtf8Error;
 use std::time::SystemTime;
 
+mod context;
+mod convention;
+mod fragment;
+mod fulltext;
+mod fuzzy;
+mod graphemes;
+mod lsp;
+mod multipattern;
+mod quote;
+mod range;
+mod regexsearch;
+mod search;
+mod snap;
+mod snippet;
+mod units;
+#[cfg(feature = "watch")]
+mod watch;
+
+pub use context::{ConcordanceLine, ConcordanceOptions, ContextUnit, TextWithContext};
+pub use convention::{Convention, ConventionView};
+pub use fragment::{FragmentScheme, IntegrityCheck, TextFragment};
+pub use fulltext::SuffixArray;
+pub use fuzzy::{FuzzyIter, FuzzyMatch, MAX_FUZZY_PATTERN};
+pub use graphemes::Graphemes;
+pub use lsp::{LspPosition, PositionEncoding};
+pub use multipattern::{MatcherOptions, PatternMatch, PatternMatchIter, PatternMatcher};
+pub use quote::{Ambiguity, QuoteOptions, TextQuoteSelector};
+pub use range::{Offset, TextRange};
+pub use regexsearch::{RegexIter, RegexMatch, DEFAULT_REGEX_WINDOW};
+pub use search::FindIter;
+pub use snap::{Boundary, SnapDirection};
+pub use snippet::{Overlap, SnippetFormat, SnippetOptions};
+pub use units::{BytePos, ByteRange, CharPos, CharRange, LineNo, LineRange, TypedRange};
+#[cfg(feature = "watch")]
+pub use watch::{WatchCallback, WatchPolicy};
+
 /// Handle to a frame (index in a vector)
 type FrameHandle = u32;
 
 #[derive(Debug)]
 pub enum Error {
-    OutOfBoundsError { begin: isize, end: isize },
+    OutOfBoundsError {
+        begin: isize,
+        end: isize,
+    },
     InvalidUtf8Byte(usize),
     EmptyText,
     IOError(std::io::Error),
@@ -34,6 +73,36 @@ pub enum Error {
     IndexError,
     NotLoaded,
     NoLineIndex,
+    NoUtf16Index,
+    /// The UTF-16 offset falls within a surrogate pair
+    InvalidUtf16Offset(usize),
+    NoGraphemeIndex,
+    NoFullTextIndex,
+    /// The character is not at the start of a grapheme cluster
+    InvalidGraphemeBoundary(usize),
+    /// The text file on disk was modified after it was indexed, the range refers to the affected bytes
+    SourceModified {
+        begin: usize,
+        end: usize,
+    },
+    /// The fragment identifier could not be parsed
+    InvalidFragment(String),
+    /// The length given in a fragment identifier does not match the text file
+    LengthMismatch {
+        expected: usize,
+        found: usize,
+    },
+    /// The MD5 hash given in a fragment identifier does not match the text file
+    Md5Mismatch {
+        expected: [u8; 16],
+        found: [u8; 16],
+    },
+    /// The selector is not valid (e.g. it selects no text)
+    InvalidSelector,
+    /// The selector matches at more than one place
+    AmbiguousSelector,
+    /// A search pattern is not valid
+    InvalidPattern(String),
 }
 
 impl fmt::Display for Error {
@@ -53,6 +122,32 @@ impl fmt::Display for Error {
             Self::InvalidHandle => write!(f, "Invalid handle"),
             Self::IndexError => write!(f, "Index I/O error"),
             Self::NoLineIndex => write!(f, "No line index enabled"),
+            Self::NoUtf16Index => write!(f, "No UTF-16 index enabled"),
+            Self::NoGraphemeIndex => write!(f, "No grapheme index enabled"),
+            Self::NoFullTextIndex => write!(f, "No full-text index enabled"),
+            Self::InvalidGraphemeBoundary(charpos) => write!(
+                f,
+                "Character does not correspond with a grapheme cluster boundary ({})",
+                charpos
+            ),
+            Self::InvalidUtf16Offset(offset) => write!(
+                f,
+                "UTF-16 offset does not correspond with a character boundary ({})",
+                offset
+            ),
+            Self::SourceModified { begin, end } => write!(
+                f,
+                "Text file was modified after it was indexed (bytes {}-{})",
+                begin, end
+            ),
+            Self::InvalidFragment(msg) => write!(f, "Invalid fragment identifier: {}", msg),
+            Self::LengthMismatch { expected, found } => {
+                write!(f, "Length mismatch: expected {}, found {}", expected, found)
+            }
+            Self::Md5Mismatch { .. } => write!(f, "MD5 mismatch"),
+            Self::InvalidSelector => write!(f, "Invalid selector"),
+            Self::AmbiguousSelector => write!(f, "Selector is ambiguous"),
+            Self::InvalidPattern(msg) => write!(f, "Invalid pattern: {}", msg),
         }
     }
 }
@@ -124,6 +219,20 @@ pub struct TextFile {
 
     /// Modification time (unix timestamp)
     metadata: std::fs::Metadata,
+
+    /// Options
+    options: TextFileOptions,
+
+    /// The path to the index file (if any)
+    indexpath: Option<PathBuf>,
+
+    /// The state of the SHA-256 checksum computation at the end of the indexed text (if known),
+    /// allows `refresh_appended()` to extend the checksum without reading the existing text again
+    hasher: Option<Hash>,
+
+    /// Watches the file on disk for modifications (if enabled)
+    #[cfg(feature = "watch")]
+    watcher: Option<watch::FileWatcher>,
 }
 
 /// A frame is a fragment of loaded text
@@ -154,6 +263,26 @@ struct PositionIndex {
     /// Maps lines to bytes (if enabled)
     #[n(4)]
     lines: Lines,
+
+    /// SHA256 checksums per fixed-size block (if enabled)
+    #[n(5)]
+    blocks: Option<BlockChecksums>,
+
+    /// UTF-16 code unit offsets for each item in `positions` (if enabled)
+    #[n(6)]
+    utf16: Option<Utf16Positions>,
+
+    /// Maps grapheme clusters to characters (if enabled)
+    #[n(7)]
+    graphemes: Option<Graphemes>,
+
+    /// Suffix array for full-text search (if enabled)
+    #[n(8)]
+    fulltext: Option<SuffixArray>,
+
+    /// SHA256 checksum of the last `TAILSIZE` bytes of the contents, to cheaply check that text was only appended
+    #[n(9)]
+    tailchecksum: Option<[u8; 32]>,
 }
 
 impl Default for PositionIndex {
@@ -164,6 +293,11 @@ impl Default for PositionIndex {
             lines: Lines::default(),
             positions: Positions::Large(Vec::default()),
             checksum: Default::default(),
+            blocks: None,
+            utf16: None,
+            graphemes: None,
+            fulltext: None,
+            tailchecksum: None,
         }
     }
 }
@@ -200,6 +334,10 @@ impl Positions {
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn bytepos(&self, index: usize) -> Option<usize> {
         match self {
             Self::Small(positions) => positions.get(index).map(|x| x.bytepos as usize),
@@ -256,6 +394,31 @@ impl Positions {
         }
     }
 
+    /// Converts the positions to a wider variant if that is needed to hold offsets for a file of the given size
+    pub fn widen(&mut self, filesize: usize) {
+        let mut widened = Self::new(filesize);
+        if widened.width() <= self.width() {
+            return;
+        }
+        for i in 0..self.len() {
+            widened.push(
+                self.charpos(i).expect("position should exist"),
+                self.bytepos(i).expect("position should exist"),
+                self.size(i).expect("position should exist"),
+            );
+        }
+        *self = widened;
+    }
+
+    /// Returns the width of the underlying integer type, in bytes
+    fn width(&self) -> usize {
+        match self {
+            Self::Small(_) => 2,
+            Self::Large(_) => 4,
+            Self::Huge(_) => 8,
+        }
+    }
+
     pub fn push(&mut self, charpos: usize, bytepos: usize, charsize: u8) {
         match self {
             Self::Small(positions) => positions.push(PositionData {
@@ -311,6 +474,11 @@ impl Lines {
         }
     }
 
+    /// Returns true if there are no lines (i.e. no line index was computed)
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// Returns the byte position where a line begins
     pub fn get(&self, index: usize) -> Option<usize> {
         match self {
@@ -327,6 +495,45 @@ impl Lines {
             Self::Huge(positions) => positions.push(line as u64),
         }
     }
+
+    /// Searches for the line that begins at the given byte position, see `slice::binary_search()` for the semantics of the result
+    pub fn binary_search(&self, bytepos: usize) -> Result<usize, usize> {
+        match self {
+            Self::Small(positions) => positions.binary_search_by_key(&bytepos, |x| *x as usize),
+            Self::Large(positions) => positions.binary_search_by_key(&bytepos, |x| *x as usize),
+            Self::Huge(positions) => positions.binary_search_by_key(&bytepos, |x| *x as usize),
+        }
+    }
+
+    /// Removes the last line and returns its byte position
+    pub fn pop(&mut self) -> Option<usize> {
+        match self {
+            Self::Small(positions) => positions.pop().map(|x| x as usize),
+            Self::Large(positions) => positions.pop().map(|x| x as usize),
+            Self::Huge(positions) => positions.pop().map(|x| x as usize),
+        }
+    }
+
+    /// Converts the lines to a wider variant if that is needed to hold offsets for a file of the given size
+    pub fn widen(&mut self, filesize: usize) {
+        let mut widened = Self::new(filesize);
+        if widened.width() <= self.width() {
+            return;
+        }
+        for i in 0..self.len() {
+            widened.push(self.get(i).expect("line should exist"));
+        }
+        *self = widened;
+    }
+
+    /// Returns the width of the underlying integer type, in bytes
+    fn width(&self) -> usize {
+        match self {
+            Self::Small(_) => 2,
+            Self::Large(_) => 4,
+            Self::Huge(_) => 8,
+        }
+    }
 }
 
 impl Default for Lines {
@@ -335,25 +542,327 @@ impl Default for Lines {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Debug, Clone, Decode, Encode)]
+/// Abstraction over differently sized vectors
+/// Holds the UTF-16 code unit offset for each item in `Positions`, which allows
+/// conversion between UTF-16 offsets and character offsets.
+pub enum Utf16Positions {
+    #[n(0)]
+    Small(#[n(0)] Vec<u16>),
+
+    #[n(1)]
+    Large(#[n(0)] Vec<u32>),
+
+    #[n(2)]
+    Huge(#[n(0)] Vec<u64>),
+}
+
+impl Utf16Positions {
+    /// Computes the UTF-16 offsets from the positions. A text never has more UTF-16 code units than UTF-8 bytes, so the filesize determines the width.
+    pub fn new(positions: &Positions, filesize: usize) -> Self {
+        let mut utf16positions = if filesize < 65536 {
+            Self::Small(Vec::with_capacity(positions.len()))
+        } else if filesize < 4294967296 {
+            Self::Large(Vec::with_capacity(positions.len()))
+        } else {
+            Self::Huge(Vec::with_capacity(positions.len()))
+        };
+        let mut utf16pos = 0;
+        for i in 0..positions.len() {
+            if i > 0 {
+                let chars = positions.charpos(i).expect("position should exist")
+                    - positions.charpos(i - 1).expect("position should exist");
+                utf16pos +=
+                    chars * utf16_width(positions.size(i - 1).expect("position should exist"));
+            }
+            utf16positions.push(utf16pos);
+        }
+        utf16positions
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Small(positions) => positions.len(),
+            Self::Large(positions) => positions.len(),
+            Self::Huge(positions) => positions.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the UTF-16 offset for the given index
+    pub fn get(&self, index: usize) -> Option<usize> {
+        match self {
+            Self::Small(positions) => positions.get(index).map(|x| *x as usize),
+            Self::Large(positions) => positions.get(index).map(|x| *x as usize),
+            Self::Huge(positions) => positions.get(index).map(|x| *x as usize),
+        }
+    }
+
+    pub fn binary_search(&self, utf16pos: usize) -> Result<usize, usize> {
+        match self {
+            Self::Small(positions) => positions.binary_search_by_key(&utf16pos, |x| *x as usize),
+            Self::Large(positions) => positions.binary_search_by_key(&utf16pos, |x| *x as usize),
+            Self::Huge(positions) => positions.binary_search_by_key(&utf16pos, |x| *x as usize),
+        }
+    }
+
+    pub fn push(&mut self, utf16pos: usize) {
+        match self {
+            Self::Small(positions) => positions.push(utf16pos as u16),
+            Self::Large(positions) => positions.push(utf16pos as u32),
+            Self::Huge(positions) => positions.push(utf16pos as u64),
+        }
+    }
+}
+
+/// Returns the number of UTF-16 code units for a character of the given UTF-8 size
+fn utf16_width(utf8size: u8) -> usize {
+    if utf8size == 4 {
+        2
+    } else {
+        1
+    }
+}
+
+#[derive(Debug, Clone, Decode, Encode)]
+/// SHA-256 checksums for consecutive fixed-size blocks of the text file.
+/// The last block may be smaller than the block size.
+pub struct BlockChecksums {
+    /// Size of each block in bytes
+    #[n(0)]
+    blocksize: usize,
+
+    /// Checksums per block
+    #[n(1)]
+    checksums: Vec<[u8; 32]>,
+}
+
+impl BlockChecksums {
+    /// Returns the size of each block in bytes
+    pub fn blocksize(&self) -> usize {
+        self.blocksize
+    }
+
+    /// Returns the number of blocks
+    pub fn len(&self) -> usize {
+        self.checksums.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.checksums.is_empty()
+    }
+
+    /// Returns the checksum for the given block
+    pub fn get(&self, index: usize) -> Option<&[u8; 32]> {
+        self.checksums.get(index)
+    }
+}
+
+/// Computes block checksums incrementally whilst data is streamed through it
+struct BlockHasher {
+    blocksize: usize,
+    current: Hash,
+    filled: usize,
+    checksums: Vec<[u8; 32]>,
+}
+
+impl BlockHasher {
+    fn new(blocksize: usize) -> Self {
+        Self {
+            blocksize,
+            current: Hash::new(),
+            filled: 0,
+            checksums: Vec::new(),
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let n = (self.blocksize - self.filled).min(data.len());
+            self.current.update(&data[..n]);
+            self.filled += n;
+            data = &data[n..];
+            if self.filled == self.blocksize {
+                let hash = std::mem::replace(&mut self.current, Hash::new());
+                self.checksums.push(hash.finalize());
+                self.filled = 0;
+            }
+        }
+    }
+
+    /// Resumes computing block checksums after the given ones, `tail` holds the bytes of the last (partial) block, if any.
+    fn resume(blocks: &BlockChecksums, tail: &[u8]) -> Self {
+        let mut checksums = blocks.checksums.clone();
+        if !tail.is_empty() {
+            checksums.pop();
+        }
+        let mut blockhasher = Self {
+            blocksize: blocks.blocksize,
+            current: Hash::new(),
+            filled: 0,
+            checksums,
+        };
+        blockhasher.update(tail);
+        blockhasher
+    }
+
+    fn finish(mut self) -> BlockChecksums {
+        if self.filled > 0 {
+            self.checksums.push(self.current.finalize());
+        }
+        BlockChecksums {
+            blocksize: self.blocksize,
+            checksums: self.checksums,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 /// Text file mode.
 pub enum TextFileMode {
     /// Do not compute a line index (cheapest), set this if you're not interested in line-based queries
     NoLineIndex,
 
     /// Compute a line index (takes memory and cpu time), allows queries based on line ranges
+    #[default]
     WithLineIndex,
 }
 
-impl Default for TextFileMode {
-    fn default() -> Self {
-        Self::WithLineIndex
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// The result of verifying a text file on disk against the information that was recorded when it was indexed.
+pub enum Verification {
+    /// The file on disk matches
+    Ok,
+
+    /// The SHA-256 checksum of the file on disk does not match the stored checksum
+    ChecksumMismatch { expected: [u8; 32], found: [u8; 32] },
+
+    /// The size of the file on disk (in bytes) does not match the indexed size
+    SizeMismatch { expected: u64, found: u64 },
+
+    /// The modification time of the file on disk does not match the one at the time the file was associated
+    ModificationTimeMismatch {
+        expected: SystemTime,
+        found: SystemTime,
+    },
+}
+
+impl Verification {
+    /// Returns true if the verification passed
+    pub fn is_ok(&self) -> bool {
+        matches!(self, Self::Ok)
+    }
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Ok => write!(f, "ok"),
+            Self::ChecksumMismatch { expected, found } => write!(
+                f,
+                "checksum mismatch (expected {:x}, found {:x})",
+                HexDigest(expected),
+                HexDigest(found)
+            ),
+            Self::SizeMismatch { expected, found } => write!(
+                f,
+                "size mismatch (expected {} bytes, found {} bytes)",
+                expected, found
+            ),
+            Self::ModificationTimeMismatch { .. } => write!(f, "modification time mismatch"),
+        }
+    }
+}
+
+/// The default block size (in bytes) for block checksums
+pub const DEFAULT_BLOCKSIZE: usize = 65536;
+
+/// The number of bytes at the end of the text that `refresh_appended()` checks to see if the existing text was left untouched
+const TAILSIZE: usize = 4096;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+/// Options for associating with a text file, this extends `TextFileMode` with further (opt-in) features.
+pub struct TextFileOptions {
+    mode: TextFileMode,
+    blocksize: Option<usize>,
+    verify_blocks: bool,
+    check_modified: bool,
+    utf16: bool,
+    graphemes: bool,
+    fulltext: bool,
+}
+
+impl TextFileOptions {
+    pub fn new(mode: TextFileMode) -> Self {
+        Self {
+            mode,
+            ..Default::default()
+        }
+    }
+
+    /// Compute and store a SHA-256 checksum for every block of the given size (in bytes) in the index.
+    pub fn with_block_checksums(mut self, blocksize: usize) -> Self {
+        self.blocksize = Some(blocksize.max(1));
+        self
+    }
+
+    /// Verify the checksums of all blocks touched whenever a frame is loaded from disk,
+    /// `Error::SourceModified` will be returned if the file was modified.
+    /// This implies block checksums, if no block size was set yet `DEFAULT_BLOCKSIZE` is used.
+    pub fn with_verify_blocks(mut self, value: bool) -> Self {
+        self.verify_blocks = value;
+        if value && self.blocksize.is_none() {
+            self.blocksize = Some(DEFAULT_BLOCKSIZE);
+        }
+        self
+    }
+
+    /// Check the size and modification time of the file on disk before loading any frame,
+    /// `Error::SourceModified` will be returned if they changed since the file was associated.
+    /// Use `TextFile::reload()` to recover.
+    pub fn with_check_modified(mut self, value: bool) -> Self {
+        self.check_modified = value;
+        self
+    }
+
+    /// Keep track of UTF-16 code unit offsets in the index (cheap), allows queries based on UTF-16 offsets as used in for instance JavaScript.
+    pub fn with_utf16(mut self, value: bool) -> Self {
+        self.utf16 = value;
+        self
+    }
+
+    /// Compute a grapheme cluster index (takes cpu time), allows queries based on extended grapheme clusters (user-perceived characters).
+    pub fn with_graphemes(mut self, value: bool) -> Self {
+        self.graphemes = value;
+        self
+    }
+
+    /// Compute a full-text index (a suffix array, takes cpu time and memory once), allows fast repeated substring queries
+    /// without scanning the text file, see `TextFile::fulltext_locate()`. Note that it covers the whole file, so
+    /// `TextFile::refresh_appended()` has to rebuild it from the whole file rather than just the appended text.
+    pub fn with_fulltext(mut self, value: bool) -> Self {
+        self.fulltext = value;
+        self
+    }
+
+    /// Returns the text file mode
+    pub fn mode(&self) -> TextFileMode {
+        self.mode
+    }
+}
+
+impl From<TextFileMode> for TextFileOptions {
+    fn from(mode: TextFileMode) -> Self {
+        Self::new(mode)
     }
 }
 
 impl TextFile {
     /// Associates with an existing text file on disk, you can optionally provide a path to an indexfile to use for caching the position index. Is such a cache is not available, the text file is scanned once and the index created.
-
+    ///
     /// * `path` - The text file
     /// * `indexpath` - The associated index file, acts as a cache if provided to prevent recomputation every time
     /// * `mode` - Additional options
@@ -361,24 +870,50 @@ impl TextFile {
         path: impl Into<PathBuf>,
         indexpath: Option<&Path>,
         mode: TextFileMode,
+    ) -> Result<Self, Error> {
+        Self::with_options(path, indexpath, TextFileOptions::new(mode))
+    }
+
+    /// Associates with an existing text file on disk, like `new()`, but with extra options.
+    ///
+    /// * `path` - The text file
+    /// * `indexpath` - The associated index file, acts as a cache if provided to prevent recomputation every time
+    /// * `options` - Options
+    pub fn with_options(
+        path: impl Into<PathBuf>,
+        indexpath: Option<&Path>,
+        options: TextFileOptions,
     ) -> Result<Self, Error> {
         let path: PathBuf = path.into();
-        let metadata = std::fs::metadata(path.as_path()).map_err(|e| Error::IOError(e))?;
+        let metadata = std::fs::metadata(path.as_path()).map_err(Error::IOError)?;
         let mut build_index = true;
         let mut positionindex = PositionIndex::default();
+        let mut hasher = None;
         if let Some(indexpath) = indexpath.as_ref() {
             if indexpath.exists() {
-                let indexmetadata = std::fs::metadata(indexpath).map_err(|e| Error::IOError(e))?;
+                let indexmetadata = std::fs::metadata(indexpath).map_err(Error::IOError)?;
                 if FileTime::from_last_modification_time(&indexmetadata)
                     >= FileTime::from_last_modification_time(&metadata)
                 {
                     positionindex = PositionIndex::from_file(indexpath)?;
-                    build_index = false;
+                    build_index = !positionindex.satisfies(&options);
                 }
             }
         }
         if build_index {
-            positionindex = PositionIndex::new(path.as_path(), metadata.len(), mode)?;
+            let checksum;
+            (positionindex, checksum) =
+                PositionIndex::new(path.as_path(), metadata.len(), &options)?;
+            hasher = Some(checksum);
+        } else if options.utf16 && positionindex.utf16.is_none() {
+            //can be derived from the cached positions
+            positionindex.utf16 = Some(Utf16Positions::new(
+                &positionindex.positions,
+                positionindex.bytesize,
+            ));
+        }
+        if !build_index && options.fulltext && positionindex.fulltext.is_none() {
+            positionindex.fulltext = Some(SuffixArray::build(path.as_path())?);
         }
         if let Some(indexpath) = indexpath.as_ref() {
             positionindex.to_file(indexpath)?;
@@ -389,9 +924,33 @@ impl TextFile {
             frametable: BTreeMap::new(),
             positionindex,
             metadata,
+            options,
+            indexpath: indexpath.map(|indexpath| indexpath.to_path_buf()),
+            hasher,
+            #[cfg(feature = "watch")]
+            watcher: None,
         })
     }
 
+    /// Rebuilds the index from the file on disk (and updates the index file if one was provided),
+    /// all loaded frames are dropped. Use this to recover after the file on disk was modified.
+    pub fn reload(&mut self) -> Result<(), Error> {
+        let metadata = std::fs::metadata(self.path.as_path()).map_err(Error::IOError)?;
+        let (positionindex, hasher) =
+            PositionIndex::new(self.path.as_path(), metadata.len(), &self.options)?;
+        if let Some(indexpath) = self.indexpath.as_ref() {
+            positionindex.to_file(indexpath)?;
+        }
+        self.positionindex = positionindex;
+        self.hasher = Some(hasher);
+        self.metadata = metadata;
+        self.frames.clear();
+        self.frametable.clear();
+        #[cfg(feature = "watch")]
+        self.reset_watched();
+        Ok(())
+    }
+
     /// Returns the filename on disk
     pub fn path(&self) -> &Path {
         self.path.as_path()
@@ -434,6 +993,34 @@ impl TextFile {
             })
     }
 
+    /// Returns the absolute byte offsets (begin, non-inclusive end) of a string slice that points into one of the loaded frames,
+    /// i.e. a slice that was returned by one of the `get` methods (or a subslice thereof).
+    /// Returns `None` if the slice does not point into memory held by this text file.
+    pub fn byte_offset_of(&self, text: &str) -> Option<(usize, usize)> {
+        let ptr = text.as_ptr() as usize;
+        self.frames.iter().find_map(|frame| {
+            let frameptr = frame.text.as_ptr() as usize;
+            if ptr >= frameptr && ptr + text.len() <= frameptr + frame.text.len() {
+                let beginbyte = frame.beginbyte + (ptr - frameptr);
+                Some((beginbyte, beginbyte + text.len()))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Returns the absolute character offsets (begin, non-inclusive end) of a string slice that points into one of the loaded frames,
+    /// i.e. a slice that was returned by one of the `get` methods (or a subslice thereof).
+    /// This is useful to map offsets found by for instance a regular expression or tokenizer on a returned slice back to the text file.
+    /// Returns `None` if the slice does not point into memory held by this text file.
+    pub fn offset_of(&self, text: &str) -> Option<(usize, usize)> {
+        let (beginbyte, endbyte) = self.byte_offset_of(text)?;
+        Some((
+            self.bytes_to_chars(beginbyte).ok()?,
+            self.bytes_to_chars(endbyte).ok()?,
+        ))
+    }
+
     /// Returns a text fragment by lines. The fragment must already be in memory or an Error::NotLoaded will be returned.
     /// Use `get_lines_or_load()` instead if the fragment might not be loaded yet.
     ///
@@ -454,10 +1041,24 @@ impl TextFile {
     /// * `end` - The end offset in unicode character points (0-indexed, non-inclusive). If 0 or negative, it is interpreted relative to the end of the text.
     pub fn get_or_load(&mut self, begin: isize, end: isize) -> Result<&str, Error> {
         let (beginchar, endchar) = self.absolute_pos(begin, end)?;
+        self.get_or_load_abs(beginchar, endchar)
+    }
+
+    /// Returns a text fragment given absolute character offsets (end is non-inclusive), the fragment will be loaded from disk into memory if needed.
+    fn get_or_load_abs(&mut self, beginchar: usize, endchar: usize) -> Result<&str, Error> {
         let beginbyte = self.chars_to_bytes(beginchar)?;
         let endbyte = self.chars_to_bytes(endchar)?;
+        self.get_or_load_bytes(beginbyte, endbyte)
+    }
+
+    /// Returns the text for a byte range, the fragment will be loaded from disk into memory if needed.
+    /// The byte range must be at valid UTF-8 character boundaries.
+    fn get_or_load_bytes(&mut self, beginbyte: usize, endbyte: usize) -> Result<&str, Error> {
         match self.framehandle(beginbyte, endbyte) {
             Some(framehandle) => {
+                //frames loaded earlier may be outdated (load_frame() does this check for new frames)
+                #[cfg(feature = "watch")]
+                self.check_watched()?;
                 let frame = self.resolve(framehandle)?;
                 Ok(
                     &frame.text.as_str()
@@ -465,12 +1066,63 @@ impl TextFile {
                 )
             }
             None => {
-                self.load_abs(beginchar, endchar)?;
-                self.get(begin, end)
+                self.load_frame(beginbyte, endbyte)?;
+                self.get_byterange_unchecked(beginbyte, endbyte)
             }
         }
     }
 
+    /// Returns a text fragment given absolute character offsets (end is non-inclusive). The fragment must already be in memory or an Error::NotLoaded will be returned.
+    fn get_abs(&self, beginchar: usize, endchar: usize) -> Result<&str, Error> {
+        let beginbyte = self.chars_to_bytes(beginchar)?;
+        let endbyte = self.chars_to_bytes(endchar)?;
+        self.get_byterange_unchecked(beginbyte, endbyte)
+    }
+
+    /// Returns a text fragment by line and column positions. The fragment must already be in memory or an Error::NotLoaded will be returned.
+    /// Use `get_or_load_linecol()` instead if the fragment might not be loaded yet.
+    ///
+    /// * `begin` - The begin position as a (line, column) tuple, both 0-indexed!!, the column is in unicode characters.
+    /// * `end` - The end position as a (line, column) tuple, both 0-indexed!!, the column is in unicode characters (non-inclusive).
+    ///
+    /// This will return Error::NoLineIndex if no line index was computed, or an Error::OutOfBoundsError if a column exceeds the length of the line.
+    pub fn get_linecol(&self, begin: (usize, usize), end: (usize, usize)) -> Result<&str, Error> {
+        let beginchar = self.linecol_to_chars(begin.0, begin.1)?;
+        let endchar = self.linecol_to_chars(end.0, end.1)?;
+        self.check_order(beginchar, endchar)?;
+        self.get_abs(beginchar, endchar)
+    }
+
+    /// Returns a text fragment by line and column positions, the fragment will be loaded from disk into memory if needed.
+    /// Use `get_linecol()` instead if you are already sure the fragment is loaded.
+    ///
+    /// * `begin` - The begin position as a (line, column) tuple, both 0-indexed!!, the column is in unicode characters.
+    /// * `end` - The end position as a (line, column) tuple, both 0-indexed!!, the column is in unicode characters (non-inclusive).
+    ///
+    /// This will return Error::NoLineIndex if no line index was computed, or an Error::OutOfBoundsError if a column exceeds the length of the line.
+    pub fn get_or_load_linecol(
+        &mut self,
+        begin: (usize, usize),
+        end: (usize, usize),
+    ) -> Result<&str, Error> {
+        let beginchar = self.linecol_to_chars(begin.0, begin.1)?;
+        let endchar = self.linecol_to_chars(end.0, end.1)?;
+        self.check_order(beginchar, endchar)?;
+        self.get_or_load_abs(beginchar, endchar)
+    }
+
+    /// Returns an error if absolute offsets are in the wrong order
+    fn check_order(&self, begin: usize, end: usize) -> Result<(), Error> {
+        if begin > end {
+            Err(Error::OutOfBoundsError {
+                begin: begin as isize,
+                end: end as isize,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
     /// Returns a text fragment, the fragment will be loaded from disk into memory if needed.
     /// Use `get_lines()` instead if you are already sure the fragment is loaded
     ///
@@ -486,18 +1138,7 @@ impl TextFile {
         } else {
             self.line_to_bytes(end)?
         };
-        if let Some(framehandle) = self.framehandle(beginbyte, endbyte) {
-            let frame = self.resolve(framehandle)?;
-            return Ok(
-                &frame.text.as_str()[(beginbyte - frame.beginbyte)..(endbyte - frame.beginbyte)]
-            );
-        }
-        self.load_frame(beginbyte, endbyte)?;
-        if let Some(frame) = self.frame(beginbyte, endbyte) {
-            Ok(&frame.text.as_str()[(beginbyte - frame.beginbyte)..(endbyte - frame.beginbyte)])
-        } else {
-            Err(Error::NotLoaded)
-        }
+        self.get_or_load_bytes(beginbyte, endbyte)
     }
 
     /// Loads a particular text range into memory
@@ -570,16 +1211,28 @@ impl TextFile {
                 end: endbyte as isize,
             });
         }
-        let mut buffer: Vec<u8> = vec![0; endbyte - beginbyte];
-        let mut file = File::open(self.path.as_path()).map_err(|e| Error::IOError(e))?;
-        file.seek(SeekFrom::Start(beginbyte as u64))
-            .map_err(|e| Error::IOError(e))?;
-        file.read_exact(&mut buffer)
-            .map_err(|e| Error::IOError(e))?;
+        #[cfg(feature = "watch")]
+        self.check_watched()?;
+        if self.options.check_modified && !self.verify_quick()?.is_ok() {
+            return Err(Error::SourceModified {
+                begin: 0,
+                end: self.positionindex.bytesize,
+            });
+        }
+        let buffer = if self.options.verify_blocks && self.positionindex.blocks.is_some() {
+            self.read_verified(beginbyte, endbyte)?
+        } else {
+            let mut buffer: Vec<u8> = vec![0; endbyte - beginbyte];
+            let mut file = File::open(self.path.as_path()).map_err(Error::IOError)?;
+            file.seek(SeekFrom::Start(beginbyte as u64))
+                .map_err(Error::IOError)?;
+            file.read_exact(&mut buffer).map_err(Error::IOError)?;
+            buffer
+        };
         let frame = TextFrame {
             beginbyte,
             endbyte,
-            text: String::from_utf8(buffer).map_err(|e| Error::Utf8Error(e))?,
+            text: String::from_utf8(buffer).map_err(Error::Utf8Error)?,
         };
         self.frames.push(frame);
         let handle = (self.frames.len() - 1) as FrameHandle;
@@ -592,31 +1245,213 @@ impl TextFile {
         Ok(handle)
     }
 
-    /// Convert a character position to byte position
-    pub fn chars_to_bytes(&self, charpos: usize) -> Result<usize, Error> {
-        match self.positionindex.positions.binary_search(charpos) {
-            Ok(index) => {
-                //exact match
-                Ok(self
-                    .positionindex
-                    .positions
-                    .bytepos(index)
-                    .expect("position should exist"))
+    /// Reads a byte range from disk and verifies the checksums of all blocks it touches.
+    /// Returns `Error::SourceModified` with the range of the first offending block if verification fails.
+    fn read_verified(&self, beginbyte: usize, endbyte: usize) -> Result<Vec<u8>, Error> {
+        let blocks = self
+            .positionindex
+            .blocks
+            .as_ref()
+            .expect("block checksums must exist");
+        if beginbyte == endbyte {
+            return Ok(Vec::new());
+        }
+        let firstblock = beginbyte / blocks.blocksize;
+        let lastblock = (endbyte - 1) / blocks.blocksize;
+        let blockbegin = firstblock * blocks.blocksize;
+        let blockend = ((lastblock + 1) * blocks.blocksize).min(self.positionindex.bytesize);
+        let mut buffer: Vec<u8> = vec![0; blockend - blockbegin];
+        let mut file = File::open(self.path.as_path()).map_err(Error::IOError)?;
+        file.seek(SeekFrom::Start(blockbegin as u64))
+            .map_err(Error::IOError)?;
+        file.read_exact(&mut buffer).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                //file was truncated
+                Error::SourceModified {
+                    begin: blockbegin,
+                    end: blockend,
+                }
+            } else {
+                Error::IOError(e)
             }
-            Err(0) => {
-                //insertion before first item should never happen **except if a file is empty**, because the first PositionData item is always the first char
-                Err(Error::EmptyText)
+        })?;
+        for (i, block) in buffer.chunks(blocks.blocksize).enumerate() {
+            let index = firstblock + i;
+            if blocks.get(index) != Some(&Hash::hash(block)) {
+                let begin = index * blocks.blocksize;
+                return Err(Error::SourceModified {
+                    begin,
+                    end: begin + block.len(),
+                });
             }
-            Err(index) => {
-                //miss, compute from the item just before, index (>0) will be the item just after the failure
-                let charpos2 = self
-                    .positionindex
-                    .positions
-                    .charpos(index - 1)
-                    .expect("position should exist");
-                let charoffset = charpos - charpos2;
-                let bytepos = self
-                    .positionindex
+        }
+        buffer.truncate(endbyte - blockbegin);
+        buffer.drain(..beginbyte - blockbegin);
+        Ok(buffer)
+    }
+
+    /// Extends the index for a file that has only been appended to since it was indexed (e.g. logs or
+    /// streaming transcripts), rather than rebuilding the whole index. The index file (if any) is updated
+    /// as well, and already loaded frames remain valid.
+    ///
+    /// The existing part of the file is validated first. This is cheap: only its size and the checksum of its last `TAILSIZE` (4096) bytes
+    /// are checked, so the cost of a refresh depends on the size of the appended text rather than that of the whole file. Only the first
+    /// refresh after loading a cached index reads the existing part in full, to validate it against the stored checksum.
+    /// The exception is a full-text index (`TextFileOptions::with_fulltext()`): a suffix array can not be extended, so
+    /// it is rebuilt from the whole file on every refresh that appended text.
+    /// If it was modified, an `Error::SourceModified` is returned; use `reload()` instead in that case.
+    /// On success, this returns the number of characters that were appended.
+    pub fn refresh_appended(&mut self) -> Result<usize, Error> {
+        if self.verify_quick()?.is_ok() {
+            return Ok(0);
+        }
+        let metadata = std::fs::metadata(self.path.as_path()).map_err(Error::IOError)?;
+        let oldsize = self.positionindex.bytesize;
+        if metadata.len() < oldsize as u64 {
+            return Err(Error::SourceModified {
+                begin: 0,
+                end: oldsize,
+            });
+        }
+        // cached block checksums are kept up to date even if they are not requested by the current options
+        let blocksize = self.options.blocksize.or(self
+            .positionindex
+            .blocks
+            .as_ref()
+            .map(|blocks| blocks.blocksize));
+        let resumable_blocks = match (blocksize, self.positionindex.blocks.as_ref()) {
+            (Some(blocksize), Some(blocks)) => blocks.blocksize == blocksize,
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        let mut file = File::open(self.path.as_path()).map_err(Error::IOError)?;
+        let (mut checksum, mut blockhasher, lastbyte) =
+            match (self.hasher, self.positionindex.tailchecksum) {
+                (Some(hasher), Some(tailchecksum)) if resumable_blocks => {
+                    //validate the existing part cheaply, by the checksum of its tail only
+                    let tailbegin = oldsize.saturating_sub(TAILSIZE);
+                    let blockbegin =
+                        blocksize.map_or(oldsize, |blocksize| oldsize / blocksize * blocksize);
+                    let readbegin = tailbegin.min(blockbegin);
+                    let mut buffer: Vec<u8> = vec![0; oldsize - readbegin];
+                    file.seek(SeekFrom::Start(readbegin as u64))
+                        .map_err(Error::IOError)?;
+                    file.read_exact(&mut buffer).map_err(Error::IOError)?;
+                    if Hash::hash(&buffer[tailbegin - readbegin..]) != tailchecksum {
+                        return Err(Error::SourceModified {
+                            begin: tailbegin,
+                            end: oldsize,
+                        });
+                    }
+                    let blockhasher = self.positionindex.blocks.as_ref().map(|blocks| {
+                        BlockHasher::resume(blocks, &buffer[blockbegin - readbegin..])
+                    });
+                    (hasher, blockhasher, buffer.last().copied())
+                }
+                _ => {
+                    //validate the existing part in full, this is only needed once as the checksum state is kept afterwards
+                    let mut reader = BufReader::new(&mut file);
+                    let mut checksum = Hash::new();
+                    let mut blockhasher = blocksize.map(BlockHasher::new);
+                    let mut lastbyte = None;
+                    let mut remainder = oldsize;
+                    while remainder > 0 {
+                        let buffer = reader.fill_buf().map_err(Error::IOError)?;
+                        if buffer.is_empty() {
+                            break;
+                        }
+                        let n = buffer.len().min(remainder);
+                        checksum.update(&buffer[..n]);
+                        if let Some(blockhasher) = blockhasher.as_mut() {
+                            blockhasher.update(&buffer[..n]);
+                        }
+                        lastbyte = Some(buffer[n - 1]);
+                        reader.consume(n);
+                        remainder -= n;
+                    }
+                    if remainder > 0 || checksum.finalize() != self.positionindex.checksum {
+                        return Err(Error::SourceModified {
+                            begin: 0,
+                            end: oldsize,
+                        });
+                    }
+                    (checksum, blockhasher, lastbyte)
+                }
+            };
+        file.seek(SeekFrom::Start(oldsize as u64))
+            .map_err(Error::IOError)?;
+        let mut reader = BufReader::new(file);
+        let continued = if lastbyte.is_none_or(|byte| byte == b'\n') {
+            None
+        } else {
+            Some(self.last_grapheme()?)
+        };
+
+        //extend a copy so the index remains untouched on failure
+        let mut positionindex = self.positionindex.clone();
+        positionindex.extend(
+            &mut reader,
+            metadata.len(),
+            &mut checksum,
+            blockhasher.as_mut(),
+            &self.options,
+            continued.as_deref(),
+        )?;
+        positionindex.checksum = checksum.finalize();
+        positionindex.blocks = blockhasher.map(|blockhasher| blockhasher.finish());
+        positionindex.tailchecksum =
+            Some(tail_checksum(self.path.as_path(), positionindex.bytesize)?);
+        //a suffix array can not be extended, it is rebuilt (or dropped as it is outdated)
+        positionindex.fulltext = if self.options.fulltext {
+            Some(SuffixArray::build(self.path.as_path())?)
+        } else {
+            None
+        };
+        if let Some(indexpath) = self.indexpath.as_ref() {
+            positionindex.to_file(indexpath)?;
+        }
+        let appended = positionindex.charsize - self.positionindex.charsize;
+        self.positionindex = positionindex;
+        self.hasher = Some(checksum);
+        self.metadata = metadata;
+        Ok(appended)
+    }
+
+    /// Returns the block checksums, if enabled
+    pub fn block_checksums(&self) -> Option<&BlockChecksums> {
+        self.positionindex.blocks.as_ref()
+    }
+
+    /// Returns the options
+    pub fn options(&self) -> &TextFileOptions {
+        &self.options
+    }
+
+    /// Convert a character position to byte position
+    pub fn chars_to_bytes(&self, charpos: usize) -> Result<usize, Error> {
+        match self.positionindex.positions.binary_search(charpos) {
+            Ok(index) => {
+                //exact match
+                Ok(self
+                    .positionindex
+                    .positions
+                    .bytepos(index)
+                    .expect("position should exist"))
+            }
+            Err(0) => {
+                //insertion before first item should never happen **except if a file is empty**, because the first PositionData item is always the first char
+                Err(Error::EmptyText)
+            }
+            Err(index) => {
+                //miss, compute from the item just before, index (>0) will be the item just after the failure
+                let charpos2 = self
+                    .positionindex
+                    .positions
+                    .charpos(index - 1)
+                    .expect("position should exist");
+                let charoffset = charpos - charpos2;
+                let bytepos = self
+                    .positionindex
                     .positions
                     .bytepos(index - 1)
                     .expect("position should exist")
@@ -723,6 +1558,251 @@ impl TextFile {
         Ok((beginbyte, endbyte))
     }
 
+    /// Convert a character position to a UTF-16 code unit offset.
+    /// This will return an `Error::NoUtf16Index` if UTF-16 offsets are not tracked (see `TextFileOptions::with_utf16()`).
+    pub fn chars_to_utf16(&self, charpos: usize) -> Result<usize, Error> {
+        let utf16 = self
+            .positionindex
+            .utf16
+            .as_ref()
+            .ok_or(Error::NoUtf16Index)?;
+        let positions = &self.positionindex.positions;
+        if charpos > self.positionindex.charsize {
+            return Err(Error::OutOfBoundsError {
+                begin: charpos as isize,
+                end: 0,
+            });
+        }
+        match positions.binary_search(charpos) {
+            Ok(index) => Ok(utf16.get(index).expect("position should exist")),
+            Err(0) => Err(Error::EmptyText),
+            Err(index) => {
+                let charoffset =
+                    charpos - positions.charpos(index - 1).expect("position should exist");
+                Ok(utf16.get(index - 1).expect("position should exist")
+                    + charoffset
+                        * utf16_width(positions.size(index - 1).expect("position should exist")))
+            }
+        }
+    }
+
+    /// Convert a UTF-16 code unit offset to a character position. Returns `Error::InvalidUtf16Offset` if the offset falls within a surrogate pair.
+    /// This will return an `Error::NoUtf16Index` if UTF-16 offsets are not tracked (see `TextFileOptions::with_utf16()`).
+    pub fn utf16_to_chars(&self, utf16pos: usize) -> Result<usize, Error> {
+        if utf16pos > self.len_utf16()? {
+            return Err(Error::OutOfBoundsError {
+                begin: utf16pos as isize,
+                end: 0,
+            });
+        }
+        let utf16 = self
+            .positionindex
+            .utf16
+            .as_ref()
+            .ok_or(Error::NoUtf16Index)?;
+        let positions = &self.positionindex.positions;
+        match utf16.binary_search(utf16pos) {
+            Ok(index) => Ok(positions.charpos(index).expect("position should exist")),
+            Err(0) => Err(Error::EmptyText),
+            Err(index) => {
+                let offset = utf16pos - utf16.get(index - 1).expect("position should exist");
+                let width = utf16_width(positions.size(index - 1).expect("position should exist"));
+                if offset % width == 0 {
+                    Ok(positions.charpos(index - 1).expect("position should exist")
+                        + offset / width)
+                } else {
+                    Err(Error::InvalidUtf16Offset(utf16pos))
+                }
+            }
+        }
+    }
+
+    /// Returns the length of the total text file in UTF-16 code units.
+    /// This will return an `Error::NoUtf16Index` if UTF-16 offsets are not tracked (see `TextFileOptions::with_utf16()`).
+    pub fn len_utf16(&self) -> Result<usize, Error> {
+        if self.positionindex.utf16.is_none() {
+            return Err(Error::NoUtf16Index);
+        } else if self.positionindex.charsize == 0 {
+            return Ok(0);
+        }
+        self.chars_to_utf16(self.positionindex.charsize)
+    }
+
+    /// Converts relative UTF-16 offsets to absolute ones, in the same way as `absolute_pos()` does for character offsets.
+    ///
+    /// * `begin` - The begin offset in UTF-16 code units (0-indexed). If negative, it is interpreted relative to the end of the text.
+    /// * `end` - The end offset in UTF-16 code units (0-indexed, non-inclusive). If 0 or negative, it is interpreted relative to the end of the text.
+    pub fn absolute_utf16_pos(
+        &self,
+        mut begin: isize,
+        mut end: isize,
+    ) -> Result<(usize, usize), Error> {
+        let len = self.len_utf16()? as isize;
+        if begin < 0 {
+            begin += len;
+        }
+
+        if end <= 0 {
+            end += len;
+        }
+
+        if begin < 0 || end < 0 || begin > end {
+            return Err(Error::OutOfBoundsError { begin, end });
+        }
+
+        Ok((begin as usize, end as usize))
+    }
+
+    /// Returns a text fragment by UTF-16 offsets. The fragment must already be in memory or an Error::NotLoaded will be returned.
+    /// Use `get_or_load_utf16()` instead if the fragment might not be loaded yet.
+    ///
+    /// * `begin` - The begin offset in UTF-16 code units (0-indexed). If negative, it is interpreted relative to the end of the text.
+    /// * `end` - The end offset in UTF-16 code units (0-indexed, non-inclusive). If 0 or negative, it is interpreted relative to the end of the text.
+    ///
+    /// This will return an `Error::NoUtf16Index` if UTF-16 offsets are not tracked (see `TextFileOptions::with_utf16()`).
+    pub fn get_utf16(&self, begin: isize, end: isize) -> Result<&str, Error> {
+        let (begin, end) = self.absolute_utf16_pos(begin, end)?;
+        self.get_abs(self.utf16_to_chars(begin)?, self.utf16_to_chars(end)?)
+    }
+
+    /// Returns a text fragment by UTF-16 offsets, the fragment will be loaded from disk into memory if needed.
+    /// Use `get_utf16()` instead if you are already sure the fragment is loaded.
+    ///
+    /// * `begin` - The begin offset in UTF-16 code units (0-indexed). If negative, it is interpreted relative to the end of the text.
+    /// * `end` - The end offset in UTF-16 code units (0-indexed, non-inclusive). If 0 or negative, it is interpreted relative to the end of the text.
+    ///
+    /// This will return an `Error::NoUtf16Index` if UTF-16 offsets are not tracked (see `TextFileOptions::with_utf16()`).
+    pub fn get_or_load_utf16(&mut self, begin: isize, end: isize) -> Result<&str, Error> {
+        let (begin, end) = self.absolute_utf16_pos(begin, end)?;
+        let beginchar = self.utf16_to_chars(begin)?;
+        let endchar = self.utf16_to_chars(end)?;
+        self.get_or_load_abs(beginchar, endchar)
+    }
+
+    /// Returns the line (0-indexed!!) that the given UTF-8 byte position is on.
+    /// This will return an `Error::NoLineIndex` if no line index was computed/loaded.
+    pub fn byte_to_line(&self, bytepos: usize) -> Result<usize, Error> {
+        if self.positionindex.lines.is_empty() {
+            return Err(Error::NoLineIndex);
+        }
+        if bytepos > self.positionindex.bytesize {
+            return Err(Error::OutOfBoundsError {
+                begin: bytepos as isize,
+                end: 0,
+            });
+        }
+        match self.positionindex.lines.binary_search(bytepos) {
+            //the end of the text only starts a new (empty) line if the text ends with a newline, otherwise it is on the last real line
+            Ok(line)
+                if line > 0
+                    && line + 1 == self.positionindex.lines.len()
+                    && !self.ends_with_newline()? =>
+            {
+                Ok(line - 1)
+            }
+            Ok(line) => Ok(line),
+            //the first line always starts at 0, so this never underflows
+            Err(line) => Ok(line - 1),
+        }
+    }
+
+    /// Returns the line (0-indexed!!) that the given character position is on.
+    /// This will return an `Error::NoLineIndex` if no line index was computed/loaded.
+    pub fn char_to_line(&self, charpos: usize) -> Result<usize, Error> {
+        self.byte_to_line(self.chars_to_bytes(charpos)?)
+    }
+
+    /// Returns the line and column (both 0-indexed!!) that the given character position is on.
+    /// The column is expressed in unicode characters.
+    /// This will return an `Error::NoLineIndex` if no line index was computed/loaded.
+    pub fn char_to_linecol(&self, charpos: usize) -> Result<(usize, usize), Error> {
+        let line = self.char_to_line(charpos)?;
+        let linebegin = self.bytes_to_chars(
+            self.positionindex
+                .lines
+                .get(line)
+                .expect("line should exist"),
+        )?;
+        Ok((line, charpos - linebegin))
+    }
+
+    /// Converts a line and column (both 0-indexed!!) to a character position, the column is in unicode characters.
+    /// A column may point just after the last character of the line (excluding the newline), but not beyond,
+    /// in which case an `Error::OutOfBoundsError` is returned.
+    /// This will return an `Error::NoLineIndex` if no line index was computed/loaded.
+    pub fn linecol_to_chars(&self, line: usize, col: usize) -> Result<usize, Error> {
+        let (linebegin, lineend) = self.line_chars(line)?;
+        if linebegin + col > lineend {
+            return Err(Error::OutOfBoundsError {
+                begin: (linebegin + col) as isize,
+                end: 0,
+            });
+        }
+        Ok(linebegin + col)
+    }
+
+    /// Returns the absolute character range of a line (0-indexed!!), excluding the trailing newline (if any)
+    fn line_chars(&self, line: usize) -> Result<(usize, usize), Error> {
+        let num_lines = self.positionindex.lines.len();
+        if num_lines == 0 {
+            return Err(Error::NoLineIndex);
+        }
+        let beginbyte = self
+            .positionindex
+            .lines
+            .get(line)
+            .ok_or(Error::OutOfBoundsError {
+                begin: line as isize,
+                end: 0,
+            })?;
+        let endbyte = self
+            .positionindex
+            .lines
+            .get(line + 1)
+            .unwrap_or(self.positionindex.bytesize);
+        let begin = self.bytes_to_chars(beginbyte)?;
+        let mut end = self.bytes_to_chars(endbyte)?;
+        // all lines but the last real one necessarily end in a newline, for that one we have to check
+        if end > begin && (line + 2 < num_lines || self.ends_with_newline()?) {
+            end -= 1;
+        }
+        Ok((begin, end))
+    }
+
+    /// Checks whether the text file ends with a newline
+    fn ends_with_newline(&self) -> Result<bool, Error> {
+        if self.positionindex.bytesize == 0 {
+            return Ok(false);
+        }
+        let lastbyte = self.positionindex.bytesize - 1;
+        //compare bytes, the last character may be a multibyte one
+        if let Some(frame) = self.frame(lastbyte, lastbyte + 1) {
+            return Ok(frame.text.as_bytes()[lastbyte - frame.beginbyte] == b'\n');
+        }
+        let mut buffer = [0u8; 1];
+        let mut file = File::open(self.path.as_path()).map_err(Error::IOError)?;
+        file.seek(SeekFrom::Start(lastbyte as u64))
+            .map_err(Error::IOError)?;
+        file.read_exact(&mut buffer).map_err(Error::IOError)?;
+        Ok(buffer[0] == b'\n')
+    }
+
+    /// Reads the text between two absolute character offsets (end non-inclusive) directly from disk, without keeping it in memory as a frame
+    pub(crate) fn read_chars(
+        &self,
+        file: &mut File,
+        begin: usize,
+        end: usize,
+    ) -> Result<String, Error> {
+        let beginbyte = self.chars_to_bytes(begin)?;
+        let endbyte = self.chars_to_bytes(end)?;
+        let mut buffer: Vec<u8> = vec![0; endbyte - beginbyte];
+        file.seek(SeekFrom::Start(beginbyte as u64))
+            .map_err(Error::IOError)?;
+        file.read_exact(&mut buffer).map_err(Error::IOError)?;
+        String::from_utf8(buffer).map_err(Error::Utf8Error)
+    }
+
     /// Converts relative character offset to an absolute one. If the offset is already absolute, it will be returned as is.
     ///
     /// * `begin` - The begin offset in unicode character points (0-indexed). If negative, it is interpreted relative to the end of the text.
@@ -781,6 +1861,11 @@ impl TextFile {
         self.positionindex.charsize
     }
 
+    /// Returns true if the text file is empty
+    pub fn is_empty(&self) -> bool {
+        self.positionindex.charsize == 0
+    }
+
     /// Returns the length of the total text file in bytes
     pub fn len_utf8(&self) -> usize {
         self.positionindex.bytesize
@@ -807,62 +1892,192 @@ impl TextFile {
     pub fn checksum_digest(&self) -> String {
         format!("{:x}", HexDigest(self.checksum()))
     }
+
+    /// Verifies the file on disk against the stored SHA-256 checksum.
+    /// The file is read again in its entirety (in a streaming manner), so this is relatively expensive, see `verify_quick()` for a cheaper alternative.
+    pub fn verify(&self) -> Result<Verification, Error> {
+        let file = File::open(self.path.as_path()).map_err(Error::IOError)?;
+        let mut reader = BufReader::new(file);
+        let mut checksum = Hash::new();
+        loop {
+            let buffer = reader.fill_buf().map_err(Error::IOError)?;
+            if buffer.is_empty() {
+                //EOF
+                break;
+            }
+            checksum.update(buffer);
+            let read_bytes = buffer.len();
+            reader.consume(read_bytes);
+        }
+        let found = checksum.finalize();
+        if found == self.positionindex.checksum {
+            Ok(Verification::Ok)
+        } else {
+            Ok(Verification::ChecksumMismatch {
+                expected: self.positionindex.checksum,
+                found,
+            })
+        }
+    }
+
+    /// Verifies the file on disk by only comparing its size and modification time against what was recorded.
+    /// This is cheap as it does not read the file, but it can not detect modifications that preserve both size and modification time.
+    pub fn verify_quick(&self) -> Result<Verification, Error> {
+        let metadata = std::fs::metadata(self.path.as_path()).map_err(Error::IOError)?;
+        if metadata.len() != self.positionindex.bytesize as u64 {
+            return Ok(Verification::SizeMismatch {
+                expected: self.positionindex.bytesize as u64,
+                found: metadata.len(),
+            });
+        }
+        if let (Ok(expected), Ok(found)) = (self.metadata.modified(), metadata.modified()) {
+            if expected != found {
+                return Ok(Verification::ModificationTimeMismatch { expected, found });
+            }
+        }
+        Ok(Verification::Ok)
+    }
 }
 
 impl PositionIndex {
-    /// Build a new positionindex for a given text file
-    fn new(textfile: &Path, filesize: u64, options: TextFileMode) -> Result<Self, Error> {
-        let mut charpos = 0;
-        let mut bytepos = 0;
-        let mut prevcharsize = 0;
-        let textfile = File::open(textfile).map_err(|e| Error::IOError(e))?;
+    /// Build a new positionindex for a given text file, also returns the state of the checksum computation at the end of the text
+    fn new(path: &Path, filesize: u64, options: &TextFileOptions) -> Result<(Self, Hash), Error> {
+        let textfile = File::open(path).map_err(Error::IOError)?;
 
         // read with a line by line reader to prevent excessive read() syscalls and handle UTF-8 properly
         let mut reader = BufReader::new(textfile);
-        let mut positions = Positions::new(filesize as usize);
-        let mut lines = Lines::new(filesize as usize);
-        let mut line = String::new();
+        let mut positionindex = PositionIndex {
+            positions: Positions::new(filesize as usize),
+            lines: Lines::new(filesize as usize),
+            ..Default::default()
+        };
         let mut checksum = Hash::new();
+        let mut blockhasher = options.blocksize.map(BlockHasher::new);
+        positionindex.extend(
+            &mut reader,
+            filesize,
+            &mut checksum,
+            blockhasher.as_mut(),
+            options,
+            None,
+        )?;
+        positionindex.checksum = checksum.finalize();
+        positionindex.blocks = blockhasher.map(|blockhasher| blockhasher.finish());
+        positionindex.tailchecksum = Some(tail_checksum(path, positionindex.bytesize)?);
+        if options.fulltext {
+            positionindex.fulltext = Some(SuffixArray::build(path)?);
+        }
+        Ok((positionindex, checksum))
+    }
+
+    /// Extends the index with all text from the reader until EOF, continuing from the current end of the index.
+    ///
+    /// * `filesize` - The (new) total size of the text file in bytes
+    /// * `continued` - `None` if the text so far ends at the start of a new line (i.e. it is empty or ends with a newline), otherwise
+    ///   its last grapheme cluster (see `TextFile::last_grapheme()`), which is segmented again along with the appended text
+    fn extend(
+        &mut self,
+        reader: &mut impl BufRead,
+        filesize: u64,
+        checksum: &mut Hash,
+        mut blockhasher: Option<&mut BlockHasher>,
+        options: &TextFileOptions,
+        continued: Option<&str>,
+    ) -> Result<(), Error> {
+        let mut charpos = self.charsize;
+        let mut bytepos = self.bytesize;
+        let mut prevcharsize = if self.positions.is_empty() {
+            0
+        } else {
+            self.positions
+                .size(self.positions.len() - 1)
+                .expect("position should exist")
+        };
+        self.positions.widen(filesize as usize);
+        // a cached line index is kept up to date even if not requested
+        let lineindex = options.mode == TextFileMode::WithLineIndex || !self.lines.is_empty();
+        if lineindex {
+            self.lines.widen(filesize as usize);
+            //remove the end marker, it will be added again
+            self.lines.pop();
+        }
+        if options.graphemes && self.graphemes.is_none() {
+            self.graphemes = Some(Graphemes::default());
+        }
+        let mut line = String::new();
+        let mut linestart = continued.is_none();
+        //a grapheme cluster may span the old and the appended text
+        let mut continued = continued.filter(|cluster| !cluster.is_empty());
+        if let (Some(graphemes), Some(cluster)) = (self.graphemes.as_mut(), continued) {
+            graphemes.remove_last(cluster.chars().count());
+        }
         loop {
-            let read_bytes = reader.read_line(&mut line).map_err(|e| Error::IOError(e))?;
+            let read_bytes = reader.read_line(&mut line).map_err(Error::IOError)?;
             if read_bytes == 0 {
                 //EOF
                 break;
             } else {
                 checksum.update(&line);
-                if options == TextFileMode::WithLineIndex {
-                    lines.push(bytepos);
+                if let Some(blockhasher) = blockhasher.as_mut() {
+                    blockhasher.update(line.as_bytes());
+                }
+                if lineindex && linestart {
+                    self.lines.push(bytepos);
+                }
+                if let Some(graphemes) = self.graphemes.as_mut() {
+                    if let Some(cluster) = continued.take() {
+                        let text = format!("{}{}", cluster, line);
+                        graphemes.update(&text, charpos - cluster.chars().count());
+                    } else {
+                        graphemes.update(&line, charpos);
+                    }
                 }
                 for char in line.chars() {
                     let charsize = char.len_utf8() as u8;
                     if charsize != prevcharsize {
-                        positions.push(charpos, bytepos, charsize);
+                        self.positions.push(charpos, bytepos, charsize);
                     }
                     charpos += 1;
                     bytepos += charsize as usize;
                     prevcharsize = charsize;
                 }
+                //any subsequent read starts a new line
+                linestart = true;
                 //clear buffer for next read
                 line.clear();
             }
         }
-        let checksum = checksum.finalize();
-        if options == TextFileMode::WithLineIndex {
+        if lineindex {
             //the last 'line' marks the end position
-            lines.push(bytepos);
-        }
-        Ok(PositionIndex {
-            charsize: charpos,
-            bytesize: bytepos,
-            positions,
-            checksum,
-            lines,
-        })
+            self.lines.push(bytepos);
+        }
+        self.charsize = charpos;
+        self.bytesize = bytepos;
+        //a cached table is kept up to date even if not requested, it is cheap to derive from the positions
+        if options.utf16 || self.utf16.is_some() {
+            self.utf16 = Some(Utf16Positions::new(&self.positions, filesize as usize));
+        }
+        Ok(())
+    }
+
+    /// Checks whether this index (e.g. one loaded from cache) provides everything the options ask for
+    fn satisfies(&self, options: &TextFileOptions) -> bool {
+        if options.mode == TextFileMode::WithLineIndex && self.lines.is_empty() {
+            return false;
+        }
+        if options.graphemes && self.graphemes.is_none() {
+            return false;
+        }
+        match (options.blocksize, self.blocks.as_ref()) {
+            (Some(blocksize), Some(blocks)) => blocks.blocksize == blocksize,
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
     }
 
     /// Save a positionindex to file
-    fn to_file(&mut self, path: &Path) -> Result<(), Error> {
-        let file = File::create(path).map_err(|e| Error::IOError(e))?;
+    fn to_file(&self, path: &Path) -> Result<(), Error> {
+        let file = File::create(path).map_err(Error::IOError)?;
         let writer = BufWriter::new(file);
         let writer = minicbor::encode::write::Writer::new(writer);
         minicbor::encode(self, writer).map_err(|_| Error::IndexError)?;
@@ -871,16 +2086,25 @@ impl PositionIndex {
 
     /// Load a positionindex from file (quicker than recomputing)
     fn from_file(path: &Path) -> Result<Self, Error> {
-        let file = File::open(path).map_err(|e| Error::IOError(e))?;
+        let file = File::open(path).map_err(Error::IOError)?;
         let mut reader = BufReader::new(file);
         let mut buffer: Vec<u8> = Vec::new(); //will hold the entire CBOR file!!!
-        reader
-            .read_to_end(&mut buffer)
-            .map_err(|e| Error::IOError(e))?;
-        Ok(minicbor::decode(&buffer).map_err(|_| Error::IndexError)?)
+        reader.read_to_end(&mut buffer).map_err(Error::IOError)?;
+        minicbor::decode(&buffer).map_err(|_| Error::IndexError)
     }
 }
 
+/// Computes the SHA-256 checksum of the last `TAILSIZE` bytes of the first `bytesize` bytes of a file
+fn tail_checksum(path: &Path, bytesize: usize) -> Result<[u8; 32], Error> {
+    let tailbegin = bytesize.saturating_sub(TAILSIZE);
+    let mut buffer: Vec<u8> = vec![0; bytesize - tailbegin];
+    let mut file = File::open(path).map_err(Error::IOError)?;
+    file.seek(SeekFrom::Start(tailbegin as u64))
+        .map_err(Error::IOError)?;
+    file.read_exact(&mut buffer).map_err(Error::IOError)?;
+    Ok(Hash::hash(&buffer))
+}
+
 struct HexDigest<'a>(&'a [u8; 32]);
 
 // You can choose to implement multiple traits, like Lower and UpperHex
@@ -894,13 +2118,13 @@ impl fmt::LowerHex for HexDigest<'_> {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use std::io::Write;
     use tempfile::NamedTempFile;
 
     // all single byte-characters, for baseline testing
-    const EXAMPLE_ASCII_TEXT: &str = "
+    pub(crate) const EXAMPLE_ASCII_TEXT: &str = "
 Article 1
 
 All human beings are born free and equal in dignity and rights. They are endowed with reason and conscience and should act towards one another in a spirit of brotherhood.
@@ -919,7 +2143,7 @@ No one shall be held in slavery or servitude; slavery and the slave trade shall
 ";
 
     // multi-byte characters (mixed with single-byte)
-    const EXAMPLE_UNICODE_TEXT: &str = "
+    pub(crate) const EXAMPLE_UNICODE_TEXT: &str = "
 第一条
 
 人人生而自由,在尊严和权利上一律平等。他们赋有理性和良心,并应以兄弟关系的精神相对待。
@@ -935,29 +2159,28 @@ No one shall be held in slavery or servitude; slavery and the slave trade shall
 
 任何人不得使为奴隶或奴役;一切形式的奴隶制度和奴隶买卖,均应予以禁止。
 ";
-    const EXAMPLE_3_TEXT: &str = "ПРИВЕТ";
+    pub(crate) const EXAMPLE_3_TEXT: &str = "ПРИВЕТ";
 
-    fn setup_ascii() -> NamedTempFile {
+    pub(crate) fn setup_ascii() -> NamedTempFile {
         let mut file = tempfile::NamedTempFile::new().expect("temp file");
         write!(file, "{}", EXAMPLE_ASCII_TEXT).expect("write must work");
         file
     }
 
-    fn setup_unicode() -> NamedTempFile {
+    pub(crate) fn setup_unicode() -> NamedTempFile {
         let mut file = tempfile::NamedTempFile::new().expect("temp file");
         write!(file, "{}", EXAMPLE_UNICODE_TEXT).expect("write must work");
         file
     }
 
-    fn setup_3() -> NamedTempFile {
+    pub(crate) fn setup_3() -> NamedTempFile {
         let mut file = tempfile::NamedTempFile::new().expect("temp file");
         write!(file, "{}", EXAMPLE_3_TEXT).expect("write must work");
         file
     }
 
-    fn setup_empty() -> NamedTempFile {
-        let file = tempfile::NamedTempFile::new().expect("temp file");
-        file
+    pub(crate) fn setup_empty() -> NamedTempFile {
+        tempfile::NamedTempFile::new().expect("temp file")
     }
 
     #[test]
@@ -1294,4 +2517,608 @@ No one shall be held in slavery or servitude; slavery and the slave trade shall
         assert!(matches!(textfile.bytes_to_chars(0), Err(Error::EmptyText)));
         assert!(matches!(textfile.chars_to_bytes(0), Err(Error::EmptyText)));
     }
+
+    #[test]
+    pub fn test013_verify() {
+        let file = setup_unicode();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        assert_eq!(
+            textfile.verify().expect("verify must work"),
+            Verification::Ok
+        );
+        assert_eq!(
+            textfile.verify_quick().expect("verify must work"),
+            Verification::Ok
+        );
+    }
+
+    #[test]
+    pub fn test013_verify_mismatch() {
+        let file = setup_ascii();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        //modify the file in place, preserving its size
+        std::fs::write(
+            file.path(),
+            EXAMPLE_ASCII_TEXT.replace("Article", "ARTICLE"),
+        )
+        .expect("write must work");
+        assert!(matches!(
+            textfile.verify().expect("verify must work"),
+            Verification::ChecksumMismatch { .. }
+        ));
+    }
+
+    #[test]
+    pub fn test013_verify_quick_size_mismatch() {
+        let mut file = setup_ascii();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        write!(file, "appended").expect("write must work");
+        assert_eq!(
+            textfile.verify_quick().expect("verify must work"),
+            Verification::SizeMismatch {
+                expected: 914,
+                found: 922
+            }
+        );
+    }
+
+    #[test]
+    pub fn test014_block_checksums() {
+        let file = setup_unicode();
+        let textfile = TextFile::with_options(
+            file.path(),
+            None,
+            TextFileOptions::default().with_block_checksums(64),
+        )
+        .expect("file must load");
+        let blocks = textfile.block_checksums().expect("blocks must exist");
+        assert_eq!(blocks.blocksize(), 64);
+        assert_eq!(blocks.len(), 13); // 771 bytes
+        assert_eq!(
+            blocks.get(0),
+            Some(&Hash::hash(&EXAMPLE_UNICODE_TEXT.as_bytes()[..64]))
+        );
+        assert_eq!(
+            blocks.get(12),
+            Some(&Hash::hash(&EXAMPLE_UNICODE_TEXT.as_bytes()[768..]))
+        );
+    }
+
+    #[test]
+    pub fn test014_verify_blocks() {
+        let file = setup_ascii();
+        let mut textfile = TextFile::with_options(
+            file.path(),
+            None,
+            TextFileOptions::default()
+                .with_block_checksums(64)
+                .with_verify_blocks(true),
+        )
+        .expect("file must load");
+        assert_eq!(
+            textfile.get_or_load(1, 10).expect("text should exist"),
+            "Article 1"
+        );
+        //modify the file in place, in the third block
+        std::fs::write(
+            file.path(),
+            EXAMPLE_ASCII_TEXT.replacen("Article 2", "ARTICLE 2", 1),
+        )
+        .expect("write must work");
+        //first block is still fine
+        assert_eq!(
+            textfile.get_or_load(11, 20).expect("text should exist"),
+            "\nAll huma"
+        );
+        assert!(matches!(
+            textfile.get_or_load(150, 200),
+            Err(Error::SourceModified {
+                begin: 128,
+                end: 192
+            })
+        ));
+    }
+
+    #[test]
+    pub fn test014_block_checksums_cache() {
+        let file = setup_ascii();
+        let tmpdir = tempfile::tempdir().expect("temp dir");
+        let indexpath = tmpdir.path().join("index");
+        TextFile::new(file.path(), Some(&indexpath), Default::default()).expect("file must load");
+        //cached index has no block checksums, it must be rebuilt
+        let textfile = TextFile::with_options(
+            file.path(),
+            Some(&indexpath),
+            TextFileOptions::default().with_verify_blocks(true),
+        )
+        .expect("file must load");
+        assert_eq!(
+            textfile.block_checksums().map(|blocks| blocks.blocksize()),
+            Some(DEFAULT_BLOCKSIZE)
+        );
+    }
+
+    #[test]
+    pub fn test015_check_modified_and_reload() {
+        let mut file = setup_ascii();
+        let mut textfile = TextFile::with_options(
+            file.path(),
+            None,
+            TextFileOptions::default().with_check_modified(true),
+        )
+        .expect("file must load");
+        assert_eq!(
+            textfile.get_or_load(1, 10).expect("text should exist"),
+            "Article 1"
+        );
+        writeln!(file, "Article 5").expect("write must work");
+        assert!(matches!(
+            textfile.get_or_load(-10, 0),
+            Err(Error::SourceModified { begin: 0, end: 914 })
+        ));
+        textfile.reload().expect("reload must work");
+        assert_eq!(textfile.len(), 924);
+        assert!(matches!(textfile.get(1, 10), Err(Error::NotLoaded)));
+        assert_eq!(
+            textfile.get_or_load(-10, 0).expect("text should exist"),
+            "Article 5\n"
+        );
+    }
+
+    #[test]
+    pub fn test015_reload_updates_cache() {
+        let mut file = setup_ascii();
+        let tmpdir = tempfile::tempdir().expect("temp dir");
+        let indexpath = tmpdir.path().join("index");
+        let mut textfile = TextFile::new(file.path(), Some(&indexpath), Default::default())
+            .expect("file must load");
+        writeln!(file, "Article 5").expect("write must work");
+        textfile.reload().expect("reload must work");
+        let textfile = TextFile::new(file.path(), Some(&indexpath), Default::default())
+            .expect("file must load");
+        assert_eq!(textfile.len(), 924);
+    }
+
+    #[test]
+    pub fn test016_refresh_appended() {
+        let mut file = setup_unicode();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        assert_eq!(
+            textfile.get_or_load(1, 4).expect("text should exist"),
+            "第一条"
+        );
+        assert_eq!(textfile.refresh_appended().expect("refresh must work"), 0);
+        write!(file, "第五条\nAppended ascii text").expect("write must work");
+        assert_eq!(textfile.refresh_appended().expect("refresh must work"), 23);
+        assert_eq!(textfile.len(), 294);
+        assert_eq!(textfile.len_utf8(), 800);
+        //already loaded frames remain valid
+        assert_eq!(textfile.get(1, 4).expect("text should exist"), "第一条");
+        assert_eq!(
+            textfile.get_or_load(-24, 0).expect("text should exist"),
+            "\n第五条\nAppended ascii text"
+        );
+        assert_eq!(
+            textfile
+                .get_or_load_lines(-3, 0)
+                .expect("text should exist"),
+            "第五条\nAppended ascii text"
+        );
+
+        //the extended index must be identical to a newly built one
+        let textfile2 =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        assert_eq!(textfile.checksum(), textfile2.checksum());
+        assert_eq!(
+            textfile.positionindex.positions.len(),
+            textfile2.positionindex.positions.len()
+        );
+        assert_eq!(
+            textfile.positionindex.lines.len(),
+            textfile2.positionindex.lines.len()
+        );
+
+        //continue the last line
+        write!(file, ", continued").expect("write must work");
+        assert_eq!(textfile.refresh_appended().expect("refresh must work"), 11);
+        assert_eq!(
+            textfile
+                .get_or_load_lines(-2, 0)
+                .expect("text should exist"),
+            "Appended ascii text, continued"
+        );
+    }
+
+    #[test]
+    pub fn test016_refresh_appended_widen() {
+        let mut file = setup_3();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        assert!(matches!(
+            textfile.positionindex.positions,
+            Positions::Small(_)
+        ));
+        let appended = "ü".repeat(40000);
+        write!(file, "{}", appended).expect("write must work");
+        assert_eq!(
+            textfile.refresh_appended().expect("refresh must work"),
+            40000
+        );
+        assert!(matches!(
+            textfile.positionindex.positions,
+            Positions::Large(_)
+        ));
+        assert_eq!(
+            textfile
+                .get_or_load(-40001, -39999)
+                .expect("text should exist"),
+            "Тü"
+        );
+    }
+
+    #[test]
+    pub fn test016_refresh_appended_modified() {
+        let file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        std::fs::write(
+            file.path(),
+            format!(
+                "{}appended",
+                EXAMPLE_ASCII_TEXT.replace("Article", "ARTICLE")
+            ),
+        )
+        .expect("write must work");
+        assert!(matches!(
+            textfile.refresh_appended(),
+            Err(Error::SourceModified { begin: 0, end: 914 })
+        ));
+        assert_eq!(textfile.len(), 914);
+    }
+
+    #[test]
+    pub fn test016_refresh_appended_lines() {
+        let mut file = tempfile::NamedTempFile::new().expect("temp file");
+        write!(file, "one\ntwo\n").expect("write must work");
+        let indexfile = tempfile::NamedTempFile::new().expect("temp file");
+        std::fs::remove_file(indexfile.path()).expect("remove");
+        TextFile::new(file.path(), Some(indexfile.path()), Default::default())
+            .expect("file must load");
+        //the cached line index is loaded even though it is not requested
+        let mut textfile = TextFile::new(
+            file.path(),
+            Some(indexfile.path()),
+            TextFileMode::NoLineIndex,
+        )
+        .expect("file must load");
+        write!(file, "three\nfour\n").expect("write must work");
+        assert_eq!(textfile.refresh_appended().expect("refresh must work"), 11);
+        assert_eq!(textfile.char_to_line(15).unwrap(), 3);
+        assert_eq!(textfile.char_to_line(19).unwrap(), 4);
+        //and so is the one written back to the index file
+        let textfile = TextFile::new(file.path(), Some(indexfile.path()), Default::default())
+            .expect("file must load");
+        assert_eq!(textfile.char_to_line(15).unwrap(), 3);
+    }
+
+    #[test]
+    pub fn test016_refresh_appended_utf16() {
+        let mut file = setup_unicode();
+        let indexfile = tempfile::NamedTempFile::new().expect("temp file");
+        std::fs::remove_file(indexfile.path()).expect("remove");
+        let options = TextFileOptions::default().with_utf16(true);
+        TextFile::with_options(file.path(), Some(indexfile.path()), options.clone())
+            .expect("file must load");
+        //the cached UTF-16 table is loaded even though it is not requested
+        let mut textfile = TextFile::new(file.path(), Some(indexfile.path()), Default::default())
+            .expect("file must load");
+        write!(file, "😀 appended").expect("write must work");
+        assert_eq!(textfile.refresh_appended().expect("refresh must work"), 10);
+        let textfile2 = TextFile::with_options(file.path(), None, options).expect("file must load");
+        assert_eq!(
+            textfile.len_utf16().expect("utf16 must be tracked"),
+            textfile2.len_utf16().expect("utf16 must be tracked")
+        );
+        let len = textfile.len();
+        assert_eq!(
+            textfile.chars_to_utf16(len).expect("offset must exist"),
+            textfile2.len_utf16().expect("utf16 must be tracked")
+        );
+        assert_eq!(
+            textfile.chars_to_utf16(len - 9).expect("offset must exist"),
+            textfile2.len_utf16().expect("utf16 must be tracked") - 9
+        );
+    }
+
+    #[test]
+    pub fn test016_refresh_appended_blocks() {
+        let mut file = setup_unicode();
+        let indexfile = tempfile::NamedTempFile::new().expect("temp file");
+        std::fs::remove_file(indexfile.path()).expect("remove");
+        let options = TextFileOptions::default().with_block_checksums(100);
+        let mut textfile =
+            TextFile::with_options(file.path(), Some(indexfile.path()), options.clone())
+                .expect("file must load");
+        write!(file, "第五条\nAppended").expect("write must work");
+        assert_eq!(textfile.refresh_appended().expect("refresh must work"), 12);
+        write!(file, " text").expect("write must work");
+        assert_eq!(textfile.refresh_appended().expect("refresh must work"), 5);
+        let textfile2 =
+            TextFile::with_options(file.path(), None, options.clone()).expect("file must load");
+        assert_eq!(textfile.checksum(), textfile2.checksum());
+        let blocks = textfile.block_checksums().expect("blocks must exist");
+        let blocks2 = textfile2.block_checksums().expect("blocks must exist");
+        assert_eq!(blocks.checksums, blocks2.checksums);
+
+        //cached block checksums are maintained even if not requested, a cached index is validated in full once
+        let mut textfile = TextFile::new(file.path(), Some(indexfile.path()), Default::default())
+            .expect("file must load");
+        assert!(textfile.hasher.is_none());
+        write!(file, "!").expect("write must work");
+        assert_eq!(textfile.refresh_appended().expect("refresh must work"), 1);
+        assert!(textfile.hasher.is_some());
+        let textfile2 =
+            TextFile::with_options(file.path(), None, options.clone()).expect("file must load");
+        assert_eq!(textfile.checksum(), textfile2.checksum());
+        assert_eq!(
+            textfile
+                .block_checksums()
+                .expect("blocks must be kept")
+                .checksums,
+            textfile2
+                .block_checksums()
+                .expect("blocks must exist")
+                .checksums
+        );
+    }
+
+    #[test]
+    pub fn test016_refresh_appended_modified_tail() {
+        let text = "x".repeat(10000);
+        let mut file = tempfile::NamedTempFile::new().expect("temp file");
+        write!(file, "{}", text).expect("write must work");
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        std::fs::write(file.path(), format!("{}yz", &text[..9999])).expect("write must work");
+        assert!(matches!(
+            textfile.refresh_appended(),
+            Err(Error::SourceModified {
+                begin: 5904,
+                end: 10000
+            })
+        ));
+        assert_eq!(textfile.len(), 10000);
+    }
+
+    #[test]
+    pub fn test018_char_to_line() {
+        let file = setup_ascii();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        assert_eq!(textfile.char_to_line(0).unwrap(), 0);
+        assert_eq!(textfile.char_to_line(1).unwrap(), 1);
+        assert_eq!(textfile.char_to_line(10).unwrap(), 1); //the newline is still part of the line
+        assert_eq!(textfile.char_to_line(11).unwrap(), 2);
+        assert_eq!(textfile.char_to_line(914).unwrap(), 16);
+        assert!(textfile.char_to_line(915).is_err());
+    }
+
+    #[test]
+    pub fn test018_char_to_linecol_unicode() {
+        let file = setup_unicode();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        assert_eq!(textfile.char_to_linecol(0).unwrap(), (0, 0));
+        assert_eq!(textfile.char_to_linecol(3).unwrap(), (1, 2));
+        assert_eq!(textfile.char_to_linecol(8).unwrap(), (3, 2));
+        assert_eq!(textfile.byte_to_line(7).unwrap(), 1);
+        assert_eq!(textfile.byte_to_line(771).unwrap(), 15);
+        let (line, col) = textfile.char_to_linecol(268).unwrap();
+        let (begin, _) = textfile.absolute_line_pos(line as isize, 0).unwrap();
+        assert_eq!(begin + col, 268);
+    }
+
+    #[test]
+    pub fn test018_char_to_linecol_end() {
+        let mut file = tempfile::NamedTempFile::new().expect("temp file");
+        write!(file, "ab\ncd").expect("write must work");
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        assert_eq!(textfile.char_to_linecol(5).unwrap(), (1, 2));
+        assert_eq!(textfile.byte_to_line(5).unwrap(), 1);
+        assert_eq!(textfile.linecol_to_chars(1, 2).unwrap(), 5);
+
+        let file = setup_3();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        assert_eq!(textfile.char_to_linecol(6).unwrap(), (0, 6));
+        assert_eq!(textfile.char_to_line(6).unwrap(), 0);
+
+        //with a trailing newline, the end of the text is on a new empty line
+        let file = setup_unicode();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        assert_eq!(textfile.char_to_linecol(271).unwrap(), (15, 0));
+    }
+
+    #[test]
+    pub fn test018_char_to_line_no_line_index() {
+        let file = setup_ascii();
+        let textfile =
+            TextFile::new(file.path(), None, TextFileMode::NoLineIndex).expect("file must load");
+        assert!(matches!(textfile.char_to_line(0), Err(Error::NoLineIndex)));
+    }
+
+    #[test]
+    pub fn test019_linecol() {
+        let file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        assert_eq!(textfile.linecol_to_chars(1, 0).unwrap(), 1);
+        assert_eq!(textfile.linecol_to_chars(1, 9).unwrap(), 10);
+        assert!(matches!(
+            textfile.linecol_to_chars(1, 10),
+            Err(Error::OutOfBoundsError { .. })
+        ));
+        assert_eq!(
+            textfile
+                .get_or_load_linecol((1, 0), (1, 7))
+                .expect("text should exist"),
+            "Article"
+        );
+        assert_eq!(
+            textfile
+                .get_or_load_linecol((1, 8), (3, 3))
+                .expect("text should exist"),
+            "1\n\nAll"
+        );
+        assert_eq!(
+            textfile
+                .get_linecol((1, 0), (1, 7))
+                .expect("text should exist"),
+            "Article"
+        );
+        assert!(textfile.get_or_load_linecol((3, 3), (1, 8)).is_err());
+    }
+
+    #[test]
+    pub fn test019_linecol_unicode() {
+        let file = setup_unicode();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        assert_eq!(
+            textfile
+                .get_or_load_linecol((1, 1), (1, 3))
+                .expect("text should exist"),
+            "一条"
+        );
+        //empty line
+        assert_eq!(textfile.linecol_to_chars(2, 0).unwrap(), 5);
+        assert!(textfile.linecol_to_chars(2, 1).is_err());
+        //the last (empty) line
+        assert_eq!(textfile.linecol_to_chars(15, 0).unwrap(), 271);
+        assert!(textfile.linecol_to_chars(16, 0).is_err());
+    }
+
+    #[test]
+    pub fn test019_linecol_no_trailing_newline() {
+        let file = setup_3();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        assert_eq!(textfile.linecol_to_chars(0, 6).unwrap(), 6);
+        assert_eq!(
+            textfile
+                .get_or_load_linecol((0, 4), (0, 6))
+                .expect("text should exist"),
+            "ЕТ"
+        );
+    }
+
+    #[test]
+    pub fn test019_linecol_multibyte_end_loaded() {
+        let mut file = tempfile::NamedTempFile::new().expect("temp file");
+        write!(file, "ab\ncdé").expect("write must work");
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        textfile.get_or_load(0, 0).expect("text should exist");
+        let len = textfile.len();
+        assert_eq!(textfile.char_to_line(len).unwrap(), 1);
+        assert_eq!(textfile.char_to_linecol(len).unwrap(), (1, 3));
+        assert_eq!(textfile.linecol_to_chars(1, 3).unwrap(), len);
+        assert_eq!(
+            textfile
+                .get_or_load_linecol((1, 0), (1, 3))
+                .expect("text should exist"),
+            "cdé"
+        );
+    }
+
+    // mixes 1-byte, 2-byte, 3-byte and 4-byte (surrogate pairs in UTF-16) characters
+    pub(crate) const EXAMPLE_EMOJI_TEXT: &str = "Hello 🌍 wörld 😀😀 文字\n";
+
+    pub(crate) fn setup_emoji() -> NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().expect("temp file");
+        write!(file, "{}", EXAMPLE_EMOJI_TEXT).expect("write must work");
+        file
+    }
+
+    #[test]
+    pub fn test020_utf16() {
+        let file = setup_emoji();
+        let textfile = TextFile::with_options(
+            file.path(),
+            None,
+            TextFileOptions::default().with_utf16(true),
+        )
+        .expect("file must load");
+        let expected: Vec<u16> = EXAMPLE_EMOJI_TEXT.encode_utf16().collect();
+        assert_eq!(textfile.len_utf16().unwrap(), expected.len());
+        for (charpos, (byteoffset, _)) in EXAMPLE_EMOJI_TEXT.char_indices().enumerate() {
+            let utf16pos = EXAMPLE_EMOJI_TEXT[..byteoffset].encode_utf16().count();
+            assert_eq!(textfile.chars_to_utf16(charpos).unwrap(), utf16pos);
+            assert_eq!(textfile.utf16_to_chars(utf16pos).unwrap(), charpos);
+        }
+        //inside the surrogate pair of 🌍
+        assert!(matches!(
+            textfile.utf16_to_chars(7),
+            Err(Error::InvalidUtf16Offset(7))
+        ));
+    }
+
+    #[test]
+    pub fn test020_get_or_load_utf16() {
+        let file = setup_emoji();
+        let mut textfile = TextFile::with_options(
+            file.path(),
+            None,
+            TextFileOptions::default().with_utf16(true),
+        )
+        .expect("file must load");
+        assert_eq!(
+            textfile
+                .get_or_load_utf16(6, 14)
+                .expect("text should exist"),
+            "🌍 wörld"
+        );
+        assert_eq!(
+            textfile
+                .get_or_load_utf16(-3, 0)
+                .expect("text should exist"),
+            "文字\n"
+        );
+        assert_eq!(textfile.get_utf16(6, 8).expect("text should exist"), "🌍");
+    }
+
+    #[test]
+    pub fn test020_utf16_disabled() {
+        let file = setup_emoji();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        assert!(matches!(
+            textfile.get_or_load_utf16(0, 0),
+            Err(Error::NoUtf16Index)
+        ));
+    }
+
+    #[test]
+    pub fn test025_offset_of() {
+        let file = setup_unicode();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        textfile.load(1, 10).expect("load must work");
+        let text = textfile.get(1, 10).expect("text should exist");
+        assert_eq!(textfile.offset_of(text), Some((1, 10)));
+        //"一条" within "第一条\n..."
+        let subslice = &text[3..9];
+        assert_eq!(subslice, "一条");
+        assert_eq!(textfile.offset_of(subslice), Some((2, 4)));
+        assert_eq!(textfile.byte_offset_of(subslice), Some((4, 10)));
+        assert_eq!(textfile.offset_of("第一条"), None);
+        let copy = text.to_string();
+        assert_eq!(textfile.offset_of(copy.as_str()), None);
+    }
 }
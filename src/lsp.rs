@@ -0,0 +1,264 @@
+/*
+TextFrame
+  by Maarten van Gompel <proycon@anaproy.nl>
+  Digital Infrastructure, KNAW Humanities Cluster
+  licensed under the GNU General Public Licence v3
+*/
+
+//! Conversions between positions as used in the Language Server Protocol and character offsets
+
+use crate::{Error, TextFile};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// The encoding in which the `character` component of a `LspPosition` is expressed, as negotiated between LSP client and server.
+pub enum PositionEncoding {
+    /// Columns are UTF-8 byte offsets
+    Utf8,
+
+    /// Columns are UTF-16 code unit offsets (the LSP default), this requires UTF-16 offsets to be tracked (see `TextFileOptions::with_utf16()`)
+    #[default]
+    Utf16,
+
+    /// Columns are unicode character offsets
+    Utf32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// A position as used in the Language Server Protocol
+pub struct LspPosition {
+    /// Line (0-indexed)
+    pub line: usize,
+
+    /// Column (0-indexed) in the units of the position encoding
+    pub character: usize,
+}
+
+impl LspPosition {
+    pub fn new(line: usize, character: usize) -> Self {
+        Self { line, character }
+    }
+}
+
+impl TextFile {
+    /// Converts an LSP position to a character position.
+    /// In accordance with the LSP specification, a column beyond the end of the line (excluding the newline) defaults back to the end of the line.
+    /// Returns an `Error::InvalidUtf8Byte` or `Error::InvalidUtf16Offset` if the column falls within a character.
+    /// This will return an `Error::NoLineIndex` if no line index was computed/loaded.
+    pub fn lsp_to_chars(
+        &self,
+        position: LspPosition,
+        encoding: PositionEncoding,
+    ) -> Result<usize, Error> {
+        let (linebegin, lineend) = self.line_chars(position.line)?;
+        match encoding {
+            PositionEncoding::Utf32 => Ok((linebegin + position.character).min(lineend)),
+            PositionEncoding::Utf8 => {
+                let beginbyte = self.chars_to_bytes(linebegin)?;
+                let endbyte = self.chars_to_bytes(lineend)?;
+                self.bytes_to_chars((beginbyte + position.character).min(endbyte))
+            }
+            PositionEncoding::Utf16 => {
+                let begin = self.chars_to_utf16(linebegin)?;
+                let end = self.chars_to_utf16(lineend)?;
+                self.utf16_to_chars((begin + position.character).min(end))
+            }
+        }
+    }
+
+    /// Converts a character position to an LSP position.
+    /// This will return an `Error::NoLineIndex` if no line index was computed/loaded.
+    pub fn chars_to_lsp(
+        &self,
+        charpos: usize,
+        encoding: PositionEncoding,
+    ) -> Result<LspPosition, Error> {
+        let (line, col) = self.char_to_linecol(charpos)?;
+        let character = match encoding {
+            PositionEncoding::Utf32 => col,
+            PositionEncoding::Utf8 => {
+                self.chars_to_bytes(charpos)? - self.chars_to_bytes(charpos - col)?
+            }
+            PositionEncoding::Utf16 => {
+                self.chars_to_utf16(charpos)? - self.chars_to_utf16(charpos - col)?
+            }
+        };
+        Ok(LspPosition { line, character })
+    }
+
+    /// Returns a text fragment by LSP positions. The fragment must already be in memory or an Error::NotLoaded will be returned.
+    /// Use `get_or_load_lsp()` instead if the fragment might not be loaded yet.
+    ///
+    /// * `begin` - The begin position
+    /// * `end` - The end position (non-inclusive)
+    /// * `encoding` - The encoding in which the columns are expressed
+    pub fn get_lsp(
+        &self,
+        begin: LspPosition,
+        end: LspPosition,
+        encoding: PositionEncoding,
+    ) -> Result<&str, Error> {
+        let beginchar = self.lsp_to_chars(begin, encoding)?;
+        let endchar = self.lsp_to_chars(end, encoding)?;
+        self.check_order(beginchar, endchar)?;
+        self.get_abs(beginchar, endchar)
+    }
+
+    /// Returns a text fragment by LSP positions, the fragment will be loaded from disk into memory if needed.
+    /// Use `get_lsp()` instead if you are already sure the fragment is loaded.
+    ///
+    /// * `begin` - The begin position
+    /// * `end` - The end position (non-inclusive)
+    /// * `encoding` - The encoding in which the columns are expressed
+    pub fn get_or_load_lsp(
+        &mut self,
+        begin: LspPosition,
+        end: LspPosition,
+        encoding: PositionEncoding,
+    ) -> Result<&str, Error> {
+        let beginchar = self.lsp_to_chars(begin, encoding)?;
+        let endchar = self.lsp_to_chars(end, encoding)?;
+        self.check_order(beginchar, endchar)?;
+        self.get_or_load_abs(beginchar, endchar)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use crate::TextFileOptions;
+    use std::io::Write;
+
+    #[test]
+    pub fn test021_lsp_roundtrip() {
+        let file = setup_emoji();
+        let textfile = TextFile::with_options(
+            file.path(),
+            None,
+            TextFileOptions::default().with_utf16(true),
+        )
+        .expect("file must load");
+        for encoding in [
+            PositionEncoding::Utf8,
+            PositionEncoding::Utf16,
+            PositionEncoding::Utf32,
+        ] {
+            for charpos in 0..=textfile.len() {
+                let position = textfile.chars_to_lsp(charpos, encoding).unwrap();
+                assert_eq!(textfile.lsp_to_chars(position, encoding).unwrap(), charpos);
+            }
+        }
+        // "Hello 🌍 wörld": r is at char 10, byte 14, utf-16 unit 11
+        assert_eq!(
+            textfile.chars_to_lsp(10, PositionEncoding::Utf8).unwrap(),
+            LspPosition::new(0, 14)
+        );
+        assert_eq!(
+            textfile.chars_to_lsp(10, PositionEncoding::Utf16).unwrap(),
+            LspPosition::new(0, 11)
+        );
+        assert_eq!(
+            textfile.chars_to_lsp(10, PositionEncoding::Utf32).unwrap(),
+            LspPosition::new(0, 10)
+        );
+    }
+
+    #[test]
+    pub fn test021_lsp_roundtrip_no_trailing_newline() {
+        let mut file = tempfile::NamedTempFile::new().expect("temp file");
+        write!(file, "ab\ncd").expect("write must work");
+        let textfile = TextFile::with_options(
+            file.path(),
+            None,
+            TextFileOptions::default().with_utf16(true),
+        )
+        .expect("file must load");
+        for encoding in [
+            PositionEncoding::Utf8,
+            PositionEncoding::Utf16,
+            PositionEncoding::Utf32,
+        ] {
+            assert_eq!(
+                textfile.chars_to_lsp(5, encoding).unwrap(),
+                LspPosition::new(1, 2)
+            );
+            for charpos in 0..=textfile.len() {
+                let position = textfile.chars_to_lsp(charpos, encoding).unwrap();
+                assert_eq!(textfile.lsp_to_chars(position, encoding).unwrap(), charpos);
+            }
+        }
+        let file = setup_3();
+        let textfile = TextFile::with_options(
+            file.path(),
+            None,
+            TextFileOptions::default().with_utf16(true),
+        )
+        .expect("file must load");
+        let position = textfile.chars_to_lsp(6, PositionEncoding::Utf8).unwrap();
+        assert_eq!(position, LspPosition::new(0, 12));
+        assert_eq!(
+            textfile
+                .lsp_to_chars(position, PositionEncoding::Utf8)
+                .unwrap(),
+            6
+        );
+    }
+
+    #[test]
+    pub fn test021_lsp_clamp_and_invalid() {
+        let file = setup_emoji();
+        let textfile = TextFile::with_options(
+            file.path(),
+            None,
+            TextFileOptions::default().with_utf16(true),
+        )
+        .expect("file must load");
+        //beyond the end of the line, defaults back to the end of the line (before the newline)
+        assert_eq!(
+            textfile
+                .lsp_to_chars(LspPosition::new(0, 999), PositionEncoding::Utf32)
+                .unwrap(),
+            textfile.len() - 1
+        );
+        //within 🌍
+        assert!(matches!(
+            textfile.lsp_to_chars(LspPosition::new(0, 7), PositionEncoding::Utf16),
+            Err(Error::InvalidUtf16Offset(..))
+        ));
+        assert!(matches!(
+            textfile.lsp_to_chars(LspPosition::new(0, 7), PositionEncoding::Utf8),
+            Err(Error::InvalidUtf8Byte(..))
+        ));
+    }
+
+    #[test]
+    pub fn test021_get_or_load_lsp() {
+        let file = setup_unicode();
+        let mut textfile = TextFile::with_options(
+            file.path(),
+            None,
+            TextFileOptions::default().with_utf16(true),
+        )
+        .expect("file must load");
+        assert_eq!(
+            textfile
+                .get_or_load_lsp(
+                    LspPosition::new(1, 3),
+                    LspPosition::new(1, 9),
+                    PositionEncoding::Utf8
+                )
+                .expect("text should exist"),
+            "一条"
+        );
+        assert_eq!(
+            textfile
+                .get_or_load_lsp(
+                    LspPosition::new(1, 0),
+                    LspPosition::new(3, 2),
+                    PositionEncoding::Utf16
+                )
+                .expect("text should exist"),
+            "第一条\n\n人人"
+        );
+    }
+}
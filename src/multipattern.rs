@@ -0,0 +1,393 @@
+/*
+TextFrame
+  by Maarten van Gompel <proycon@anaproy.nl>
+  Digital Infrastructure, KNAW Humanities Cluster
+  licensed under the GNU General Public Licence v3
+*/
+
+//! Matching many patterns at once (e.g. a dictionary or gazetteer) using the Aho-Corasick algorithm, streaming through the text file
+
+use aho_corasick::{AhoCorasick, Input};
+
+use std::collections::VecDeque;
+
+use crate::search::{ChunkReader, CHUNKSIZE};
+use crate::{Error, TextFile};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// Options for a `PatternMatcher`
+pub struct MatcherOptions {
+    whole_words: bool,
+    case_insensitive: bool,
+    overlapping: bool,
+}
+
+impl MatcherOptions {
+    /// Only match whole words: a match may not be directly preceded or followed by an alphanumeric character (or underscore).
+    /// Note that this is not meaningful for scripts that do not delimit words by spaces or punctuation.
+    pub fn with_whole_words(mut self, value: bool) -> Self {
+        self.whole_words = value;
+        self
+    }
+
+    /// Match case-insensitively, this uses simple unicode lowercasing (characters whose lowercase form consists of multiple characters are left as they are)
+    pub fn with_case_insensitive(mut self, value: bool) -> Self {
+        self.case_insensitive = value;
+        self
+    }
+
+    /// Return all matches, including overlapping ones. By default, the leftmost-longest non-overlapping matches are returned.
+    pub fn with_overlapping(mut self, value: bool) -> Self {
+        self.overlapping = value;
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// A match of one of the patterns of a `PatternMatcher`, offsets are absolute character offsets (end non-inclusive)
+pub struct PatternMatch {
+    /// Index of the pattern in the order it was passed to `PatternMatcher::new()`
+    pub pattern: usize,
+    pub begin: usize,
+    pub end: usize,
+}
+
+/// Matches many patterns at once, build it once and use it with `TextFile::match_patterns()`
+pub struct PatternMatcher {
+    automaton: AhoCorasick,
+
+    /// Length of each pattern in characters
+    patternlengths: Vec<usize>,
+
+    /// Length of the longest pattern in bytes
+    maxbytes: usize,
+
+    options: MatcherOptions,
+}
+
+/// Lowercases a character, unless its lowercase form consists of multiple characters, so character offsets are preserved
+fn fold(c: char) -> char {
+    let mut lowercase = c.to_lowercase();
+    match (lowercase.next(), lowercase.next()) {
+        (Some(l), None) => l,
+        _ => c,
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+impl PatternMatcher {
+    /// Builds a matcher for the given patterns, returns an `Error::InvalidPattern` if a pattern is empty.
+    pub fn new<I, P>(patterns: I, options: MatcherOptions) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<str>,
+    {
+        let mut prepared: Vec<String> = Vec::new();
+        for pattern in patterns {
+            let pattern = pattern.as_ref();
+            if pattern.is_empty() {
+                return Err(Error::InvalidPattern("empty pattern".to_string()));
+            }
+            prepared.push(if options.case_insensitive {
+                pattern.chars().map(fold).collect()
+            } else {
+                pattern.to_string()
+            });
+        }
+        let automaton =
+            AhoCorasick::new(&prepared).map_err(|e| Error::InvalidPattern(e.to_string()))?;
+        Ok(Self {
+            automaton,
+            patternlengths: prepared.iter().map(|p| p.chars().count()).collect(),
+            maxbytes: prepared.iter().map(|p| p.len()).max().unwrap_or(0),
+            options,
+        })
+    }
+
+    /// Returns the number of patterns
+    pub fn len(&self) -> usize {
+        self.patternlengths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patternlengths.is_empty()
+    }
+
+    pub fn options(&self) -> MatcherOptions {
+        self.options
+    }
+}
+
+/// Iterator over pattern matches in a text file, see `TextFile::match_patterns()`
+pub struct PatternMatchIter<'a> {
+    textfile: &'a TextFile,
+    matcher: &'a PatternMatcher,
+
+    /// Initialised on the first call to `next()`
+    reader: Option<ChunkReader>,
+
+    /// The text currently being searched (lowercased if matching case-insensitively)
+    buffer: String,
+
+    /// Absolute character offset of the start of the buffer
+    bufferchar: usize,
+
+    /// Byte index in the buffer from where to search next
+    searchfrom: usize,
+
+    /// Matches found but not returned yet
+    queue: VecDeque<PatternMatch>,
+
+    /// Size of the chunks to read from disk
+    chunksize: usize,
+
+    done: bool,
+}
+
+impl PatternMatchIter<'_> {
+    /// Reads the next chunk and searches it
+    fn search_next_chunk(&mut self) -> Result<(), Error> {
+        if self.reader.is_none() {
+            self.reader = Some(self.textfile.chunk_reader(0, self.chunksize)?);
+        }
+        let reader = self.reader.as_mut().expect("reader initialised");
+        reader.read_chunk()?;
+        if self.matcher.options.case_insensitive {
+            self.buffer.extend(reader.buffer().chars().map(fold));
+        } else {
+            self.buffer.push_str(reader.buffer());
+        }
+        reader.discard(reader.buffer().len());
+        let done = reader.is_done();
+        self.search(done);
+        if done {
+            self.done = true;
+        } else {
+            //keep a character before the search position as context for whole-word matching
+            let keep_from = self.buffer[..self.searchfrom]
+                .char_indices()
+                .next_back()
+                .map(|(i, _)| i)
+                .unwrap_or(0);
+            self.bufferchar += self.buffer[..keep_from].chars().count();
+            self.buffer.drain(..keep_from);
+            self.searchfrom -= keep_from;
+        }
+        Ok(())
+    }
+
+    /// Searches the buffer and queues all matches that can no longer be affected by text that is not read yet
+    fn search(&mut self, done: bool) {
+        let len = self.buffer.len();
+        // a match starting before this can not be extended, nor can a longer one start before it, once more text is read
+        let settled = if done {
+            len + 1
+        } else {
+            len.saturating_sub(self.matcher.maxbytes)
+        };
+        let mut candidates: Vec<(usize, usize, usize)> = Vec::new();
+        for m in self
+            .matcher
+            .automaton
+            .find_overlapping_iter(Input::new(&self.buffer).span(self.searchfrom..len))
+        {
+            if m.start() >= settled {
+                continue;
+            }
+            if self.matcher.options.whole_words {
+                let before = if m.start() == 0 && self.bufferchar == 0 {
+                    None
+                } else {
+                    self.buffer[..m.start()].chars().next_back()
+                };
+                let after = self.buffer[m.end()..].chars().next();
+                if before.is_some_and(is_word_char) || after.is_some_and(is_word_char) {
+                    continue;
+                }
+            }
+            candidates.push((m.start(), m.end(), m.pattern().as_usize()));
+        }
+        //leftmost-longest first
+        candidates.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)).then(a.2.cmp(&b.2)));
+        let mut lastend = self.searchfrom;
+        let mut cursor = (0, self.bufferchar);
+        for (start, end, pattern) in candidates {
+            if !self.matcher.options.overlapping && start < lastend {
+                continue;
+            }
+            cursor = (
+                start,
+                cursor.1 + self.buffer[cursor.0..start].chars().count(),
+            );
+            self.queue.push_back(PatternMatch {
+                pattern,
+                begin: cursor.1,
+                end: cursor.1 + self.matcher.patternlengths[pattern],
+            });
+            lastend = end;
+        }
+        self.searchfrom = if done {
+            len
+        } else if self.matcher.options.overlapping {
+            settled.max(self.searchfrom)
+        } else {
+            settled.max(lastend)
+        };
+    }
+}
+
+impl Iterator for PatternMatchIter<'_> {
+    type Item = Result<PatternMatch, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(m) = self.queue.pop_front() {
+                return Some(Ok(m));
+            }
+            if self.done || self.matcher.is_empty() {
+                return None;
+            }
+            if let Err(e) = self.search_next_chunk() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+impl TextFile {
+    /// Returns an iterator over all matches of the patterns of a `PatternMatcher`, streaming through the text file in bounded chunks.
+    /// Matches are returned in order of their begin offset.
+    pub fn match_patterns<'a>(&'a self, matcher: &'a PatternMatcher) -> PatternMatchIter<'a> {
+        PatternMatchIter {
+            textfile: self,
+            matcher,
+            reader: None,
+            buffer: String::new(),
+            bufferchar: 0,
+            searchfrom: 0,
+            queue: VecDeque::new(),
+            chunksize: CHUNKSIZE,
+            done: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+
+    fn matches(textfile: &TextFile, matcher: &PatternMatcher) -> Vec<(usize, String)> {
+        textfile
+            .match_patterns(matcher)
+            .map(|m| m.expect("match"))
+            .map(|m| (m.pattern, EXAMPLE_ASCII_TEXT[m.begin..m.end].to_string()))
+            .collect()
+    }
+
+    #[test]
+    pub fn test032_match_patterns() {
+        let file = setup_ascii();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let matcher =
+            PatternMatcher::new(["slave", "slavery", "rights"], MatcherOptions::default()).unwrap();
+        assert_eq!(
+            matches(&textfile, &matcher),
+            vec![
+                (2, "rights".to_string()),
+                (2, "rights".to_string()),
+                (1, "slavery".to_string()),
+                (1, "slavery".to_string()),
+                (0, "slave".to_string()),
+            ]
+        );
+        let matcher = PatternMatcher::new(
+            ["slave", "slavery"],
+            MatcherOptions::default().with_overlapping(true),
+        )
+        .unwrap();
+        assert_eq!(matches(&textfile, &matcher).len(), 5);
+    }
+
+    #[test]
+    pub fn test032_match_patterns_options() {
+        let file = setup_ascii();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let matcher = PatternMatcher::new(
+            ["right", "everyone"],
+            MatcherOptions::default().with_whole_words(true),
+        )
+        .unwrap();
+        //"rights" is not a whole word match, "Everyone" does not match case-sensitively
+        assert_eq!(matches(&textfile, &matcher), vec![(0, "right".to_string())]);
+        let matcher = PatternMatcher::new(
+            ["right", "everyone"],
+            MatcherOptions::default()
+                .with_whole_words(true)
+                .with_case_insensitive(true),
+        )
+        .unwrap();
+        assert_eq!(
+            matches(&textfile, &matcher),
+            vec![
+                (1, "Everyone".to_string()),
+                (1, "Everyone".to_string()),
+                (0, "right".to_string()),
+            ]
+        );
+        assert!(PatternMatcher::new([""], MatcherOptions::default()).is_err());
+    }
+
+    #[test]
+    pub fn test032_match_patterns_unicode() {
+        let file = setup_unicode();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let matcher = PatternMatcher::new(["人人", "第一条"], MatcherOptions::default()).unwrap();
+        let found: Vec<PatternMatch> = textfile
+            .match_patterns(&matcher)
+            .map(|m| m.expect("match"))
+            .collect();
+        assert_eq!(found[0].pattern, 1);
+        assert_eq!((found[0].begin, found[0].end), (1, 4));
+        for m in found {
+            let text = textfile
+                .get_or_load(m.begin as isize, m.end as isize)
+                .expect("text should exist");
+            assert_eq!(text, ["人人", "第一条"][m.pattern]);
+        }
+    }
+
+    #[test]
+    pub fn test032_match_patterns_small_chunks() {
+        let file = setup_ascii();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        for overlapping in [false, true] {
+            let matcher = PatternMatcher::new(
+                ["the", "the slave trade", "slave", "in all their forms", "a"],
+                MatcherOptions::default()
+                    .with_whole_words(true)
+                    .with_overlapping(overlapping),
+            )
+            .unwrap();
+            let expected: Vec<PatternMatch> = textfile
+                .match_patterns(&matcher)
+                .map(|m| m.expect("match"))
+                .collect();
+            assert!(!expected.is_empty());
+            for chunksize in [4, 5, 7, 16] {
+                let mut iter = textfile.match_patterns(&matcher);
+                iter.chunksize = chunksize;
+                let found: Vec<PatternMatch> = iter.map(|m| m.expect("match")).collect();
+                assert_eq!(found, expected, "chunksize {}", chunksize);
+            }
+        }
+    }
+}
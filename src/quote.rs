@@ -0,0 +1,364 @@
+/*
+TextFrame
+  by Maarten van Gompel <proycon@anaproy.nl>
+  Digital Infrastructure, KNAW Humanities Cluster
+  licensed under the GNU General Public Licence v3
+*/
+
+//! Resolution and generation of W3C Web Annotation `TextQuoteSelector`s, which select text by quoting it (plus some context) rather than by offsets
+
+use std::fs::File;
+
+use crate::search::{ByteMatches, CHUNKSIZE};
+use crate::{Error, TextFile};
+
+/// Number of characters of context to compare at first when generating a quote, doubled as long as they are all equal
+const QUOTEWINDOW: usize = 32;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+/// A W3C Web Annotation `TextQuoteSelector`: the exact text to select, with optionally some text immediately before and after it to disambiguate
+pub struct TextQuoteSelector {
+    /// The exact text to select
+    pub exact: String,
+
+    /// The text immediately before the selection (empty if absent)
+    pub prefix: String,
+
+    /// The text immediately after the selection (empty if absent)
+    pub suffix: String,
+}
+
+impl TextQuoteSelector {
+    pub fn new(exact: impl Into<String>) -> Self {
+        Self {
+            exact: exact.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    pub fn with_suffix(mut self, suffix: impl Into<String>) -> Self {
+        self.suffix = suffix.into();
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// Determines what to return if a selector matches at multiple places in the text
+pub enum Ambiguity {
+    /// Return all matches
+    All,
+
+    /// Return only the best match: the one nearest to the position hint, or the first one if there is no hint
+    #[default]
+    Best,
+
+    /// Return an `Error::AmbiguousSelector` if there is more than one match
+    Error,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// Options for resolving a `TextQuoteSelector`
+pub struct QuoteOptions {
+    ambiguity: Ambiguity,
+    hint: Option<usize>,
+}
+
+impl QuoteOptions {
+    /// Set how to handle a selector that matches at multiple places
+    pub fn with_ambiguity(mut self, ambiguity: Ambiguity) -> Self {
+        self.ambiguity = ambiguity;
+        self
+    }
+
+    /// Set a character position near which the selection is expected (for instance its offset in an older version of the text),
+    /// with `Ambiguity::Best` the match that begins nearest to it is returned.
+    pub fn with_hint(mut self, charpos: usize) -> Self {
+        self.hint = Some(charpos);
+        self
+    }
+}
+
+impl TextFile {
+    /// Resolves a `TextQuoteSelector` to absolute character ranges (begin, non-inclusive end), streaming through the text file
+    /// without loading it into memory. The prefix and suffix, if any, must immediately precede and follow the exact text.
+    /// Returns an empty vector if the selector does not match anywhere.
+    pub fn resolve_quote(
+        &self,
+        selector: &TextQuoteSelector,
+        options: QuoteOptions,
+    ) -> Result<Vec<(usize, usize)>, Error> {
+        if selector.exact.is_empty() {
+            return Err(Error::InvalidSelector);
+        }
+        let pattern = format!("{}{}{}", selector.prefix, selector.exact, selector.suffix);
+        let mut results: Vec<(usize, usize)> = Vec::new();
+        let mut best_distance = usize::MAX;
+        for bytepos in self.quote_matches(&pattern)? {
+            let beginbyte = bytepos? + selector.prefix.len();
+            let begin = self.bytes_to_chars(beginbyte)?;
+            let end = self.bytes_to_chars(beginbyte + selector.exact.len())?;
+            match (options.ambiguity, options.hint) {
+                (Ambiguity::All, _) => results.push((begin, end)),
+                (Ambiguity::Error, _) => {
+                    if !results.is_empty() {
+                        return Err(Error::AmbiguousSelector);
+                    }
+                    results.push((begin, end));
+                }
+                (Ambiguity::Best, None) => return Ok(vec![(begin, end)]),
+                (Ambiguity::Best, Some(hint)) => {
+                    let distance = begin.abs_diff(hint);
+                    if distance < best_distance {
+                        best_distance = distance;
+                        results = vec![(begin, end)];
+                    } else if begin > hint {
+                        //matches only get further away from here on
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    /// Generates a `TextQuoteSelector` for a character range (absolute offsets, end non-inclusive), with the minimal prefix and
+    /// suffix (of equal length, unless the text begins or ends first) needed to make it match uniquely.
+    /// This streams through the text file once to find other occurrences of the exact text, and reads only the context around
+    /// those from disk, nothing is loaded into memory.
+    pub fn to_quote(&self, begin: usize, end: usize) -> Result<TextQuoteSelector, Error> {
+        if begin >= end || end > self.len() {
+            return Err(Error::OutOfBoundsError {
+                begin: begin as isize,
+                end: end as isize,
+            });
+        }
+        let len = self.len();
+        let mut file = File::open(self.path.as_path()).map_err(Error::IOError)?;
+        let exact = self.read_chars(&mut file, begin, end)?;
+        let beginbyte = self.chars_to_bytes(begin)?;
+        // every other occurrence must be ruled out by the prefix or by the suffix, which takes one character more than the context it shares with this one
+        let mut context = 0;
+        for bytepos in self.quote_matches(&exact)? {
+            let bytepos = bytepos?;
+            if bytepos == beginbyte {
+                continue;
+            }
+            let otherbegin = self.bytes_to_chars(bytepos)?;
+            let otherend = otherbegin + (end - begin);
+            let prefix = self.common_context(&mut file, begin, otherbegin, false)?;
+            let suffix = self.common_context(&mut file, end, otherend, true)?;
+            let needed = match (prefix < begin, suffix < len - end) {
+                (true, true) => prefix.min(suffix) + 1,
+                (true, false) => prefix + 1,
+                (false, true) => suffix + 1,
+                //can not happen: with all context available, only this occurrence matches
+                (false, false) => begin.max(len - end),
+            };
+            context = context.max(needed);
+        }
+        Ok(TextQuoteSelector {
+            prefix: self.read_chars(&mut file, begin.saturating_sub(context), begin)?,
+            suffix: self.read_chars(&mut file, end, (end + context).min(len))?,
+            exact,
+        })
+    }
+
+    /// Returns the number of characters that the texts immediately before (or after, if `forward` is set) two character positions have in common
+    fn common_context(
+        &self,
+        file: &mut File,
+        pos: usize,
+        otherpos: usize,
+        forward: bool,
+    ) -> Result<usize, Error> {
+        let limit = if forward {
+            self.len() - pos.max(otherpos)
+        } else {
+            pos.min(otherpos)
+        };
+        let mut window = QUOTEWINDOW;
+        loop {
+            let n = window.min(limit);
+            let common = if forward {
+                let text = self.read_chars(file, pos, pos + n)?;
+                let other = self.read_chars(file, otherpos, otherpos + n)?;
+                text.chars()
+                    .zip(other.chars())
+                    .take_while(|(c, o)| c == o)
+                    .count()
+            } else {
+                let text = self.read_chars(file, pos - n, pos)?;
+                let other = self.read_chars(file, otherpos - n, otherpos)?;
+                text.chars()
+                    .rev()
+                    .zip(other.chars().rev())
+                    .take_while(|(c, o)| c == o)
+                    .count()
+            };
+            if common < n || n == limit {
+                return Ok(common);
+            }
+            window *= 2;
+        }
+    }
+
+    /// Returns an iterator over all (possibly overlapping) byte offsets where the pattern occurs
+    fn quote_matches(&self, pattern: &str) -> Result<ByteMatches, Error> {
+        let reader = self.chunk_reader(0, CHUNKSIZE)?;
+        Ok(ByteMatches::new(reader, pattern, true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use std::io::Write;
+
+    #[test]
+    pub fn test029_resolve_quote() {
+        let file = setup_ascii();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let selector = TextQuoteSelector::new("Article");
+        let all = textfile
+            .resolve_quote(
+                &selector,
+                QuoteOptions::default().with_ambiguity(Ambiguity::All),
+            )
+            .unwrap();
+        assert_eq!(all.len(), 4);
+        assert_eq!(all[0], (1, 8));
+        assert_eq!(
+            textfile
+                .resolve_quote(&selector, QuoteOptions::default())
+                .unwrap(),
+            vec![(1, 8)]
+        );
+        assert!(matches!(
+            textfile.resolve_quote(
+                &selector,
+                QuoteOptions::default().with_ambiguity(Ambiguity::Error)
+            ),
+            Err(Error::AmbiguousSelector)
+        ));
+        //the hint selects the nearest match
+        assert_eq!(
+            textfile
+                .resolve_quote(&selector, QuoteOptions::default().with_hint(all[2].0 + 3))
+                .unwrap(),
+            vec![all[2]]
+        );
+        //the prefix/suffix disambiguates
+        let selector = TextQuoteSelector::new("Article")
+            .with_prefix("\n\n")
+            .with_suffix(" 3");
+        assert_eq!(
+            textfile
+                .resolve_quote(
+                    &selector,
+                    QuoteOptions::default().with_ambiguity(Ambiguity::Error)
+                )
+                .unwrap(),
+            vec![all[2]]
+        );
+        let selector = TextQuoteSelector::new("Article").with_suffix(" 5");
+        assert!(textfile
+            .resolve_quote(&selector, QuoteOptions::default())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    pub fn test029_to_quote() {
+        let file = setup_ascii();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        //"Article 3", ambiguous without context
+        let all = textfile
+            .resolve_quote(
+                &TextQuoteSelector::new("Article"),
+                QuoteOptions::default().with_ambiguity(Ambiguity::All),
+            )
+            .unwrap();
+        let (begin, end) = all[2];
+        let selector = textfile.to_quote(begin, end).unwrap();
+        assert_eq!(selector.exact, "Article");
+        assert_eq!(selector.prefix.chars().count(), 2);
+        assert_eq!(selector.suffix, " 3");
+        assert_eq!(
+            textfile
+                .resolve_quote(
+                    &selector,
+                    QuoteOptions::default().with_ambiguity(Ambiguity::Error)
+                )
+                .unwrap(),
+            vec![(begin, end)]
+        );
+        //already unique, no context needed
+        let selector = textfile.to_quote(12, 21).unwrap();
+        assert_eq!(selector.exact, "All human");
+        assert!(selector.prefix.is_empty() && selector.suffix.is_empty());
+    }
+
+    #[test]
+    pub fn test029_to_quote_minimal() {
+        // the occurrences of "x" share more context than is compared at first
+        let line = "abcdefghijklmnopqrstuvwxyz".repeat(3);
+        let text = format!("{line}x{line}\n1{line}x{line}\n{line}x{line}2\n");
+        let mut file = tempfile::NamedTempFile::new().expect("temp file");
+        write!(file, "{}", text).expect("write must work");
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let occurrences: Vec<usize> = text.match_indices('x').map(|(i, _)| i).collect();
+        for begin in occurrences {
+            let selector = textfile.to_quote(begin, begin + 1).unwrap();
+            let context = selector.prefix.len().max(selector.suffix.len());
+            assert_eq!(
+                textfile
+                    .resolve_quote(
+                        &selector,
+                        QuoteOptions::default().with_ambiguity(Ambiguity::Error)
+                    )
+                    .unwrap(),
+                vec![(begin, begin + 1)]
+            );
+            //one character less is not enough
+            let shorter = TextQuoteSelector::new("x")
+                .with_prefix(&text[begin.saturating_sub(context - 1)..begin])
+                .with_suffix(&text[begin + 1..(begin + context).min(text.len())]);
+            assert!(
+                textfile
+                    .resolve_quote(
+                        &shorter,
+                        QuoteOptions::default().with_ambiguity(Ambiguity::All)
+                    )
+                    .unwrap()
+                    .len()
+                    > 1
+            );
+        }
+        //nothing was loaded into memory
+        assert!(textfile.frames.is_empty());
+    }
+
+    #[test]
+    pub fn test029_quote_unicode() {
+        let file = setup_unicode();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let selector = textfile.to_quote(2, 4).unwrap();
+        assert_eq!(selector.exact, "一条");
+        assert_eq!(
+            textfile
+                .resolve_quote(&selector, QuoteOptions::default())
+                .unwrap(),
+            vec![(2, 4)]
+        );
+    }
+}
@@ -0,0 +1,318 @@
+/*
+TextFrame
+  by Maarten van Gompel <proycon@anaproy.nl>
+  Digital Infrastructure, KNAW Humanities Cluster
+  licensed under the GNU General Public Licence v3
+*/
+
+//! Ranges using Rust's range syntax, as an alternative to the begin/end offsets where 0 and negative values are relative to the end
+
+use std::ops::{
+    Bound, Range, RangeBounds, RangeFrom, RangeFull, RangeInclusive, RangeTo, RangeToInclusive,
+};
+
+use crate::{Error, TextFile};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// An offset that is either relative to the start or to the end of the text
+pub enum Offset {
+    /// Offset from the start of the text (0 is the very beginning)
+    FromStart(usize),
+
+    /// Offset from the end of the text (0 is the very end)
+    FromEnd(usize),
+}
+
+impl Offset {
+    /// Resolves the offset to an absolute one, given the total length
+    pub fn resolve(&self, len: usize) -> Result<usize, Error> {
+        match self {
+            Self::FromStart(offset) if *offset <= len => Ok(*offset),
+            Self::FromEnd(offset) if *offset <= len => Ok(len - offset),
+            Self::FromStart(offset) => Err(Error::OutOfBoundsError {
+                begin: *offset as isize,
+                end: 0,
+            }),
+            Self::FromEnd(offset) => Err(Error::OutOfBoundsError {
+                begin: -(*offset as isize),
+                end: 0,
+            }),
+        }
+    }
+}
+
+impl From<usize> for Offset {
+    fn from(offset: usize) -> Self {
+        Self::FromStart(offset)
+    }
+}
+
+/// A range that can be resolved to absolute offsets (begin, non-inclusive end).
+/// This is implemented for Rust's range types over `usize` and over `Offset`, so you can use for instance `10..20`, `..`, `..=5`, or `FromEnd(10)..`.
+pub trait TextRange {
+    /// Resolves the range to absolute offsets, given the total length
+    fn to_absolute(&self, len: usize) -> Result<(usize, usize), Error>;
+}
+
+/// Resolves range bounds to absolute offsets
+fn resolve_bounds(
+    begin: Bound<Offset>,
+    end: Bound<Offset>,
+    len: usize,
+) -> Result<(usize, usize), Error> {
+    let begin = match begin {
+        Bound::Included(offset) => offset.resolve(len)?,
+        Bound::Excluded(offset) => offset.resolve(len)? + 1,
+        Bound::Unbounded => 0,
+    };
+    let end = match end {
+        Bound::Included(offset) => offset.resolve(len)? + 1,
+        Bound::Excluded(offset) => offset.resolve(len)?,
+        Bound::Unbounded => len,
+    };
+    if begin > end || end > len {
+        return Err(Error::OutOfBoundsError {
+            begin: begin as isize,
+            end: end as isize,
+        });
+    }
+    Ok((begin, end))
+}
+
+/// Resolves a range over `usize` to absolute offsets
+fn to_absolute(range: &impl RangeBounds<usize>, len: usize) -> Result<(usize, usize), Error> {
+    resolve_bounds(
+        range.start_bound().map(|x| Offset::FromStart(*x)),
+        range.end_bound().map(|x| Offset::FromStart(*x)),
+        len,
+    )
+}
+
+/// Resolves a range over `Offset` to absolute offsets
+fn to_absolute_offset(
+    range: &impl RangeBounds<Offset>,
+    len: usize,
+) -> Result<(usize, usize), Error> {
+    resolve_bounds(
+        range.start_bound().cloned(),
+        range.end_bound().cloned(),
+        len,
+    )
+}
+
+impl TextRange for Range<usize> {
+    fn to_absolute(&self, len: usize) -> Result<(usize, usize), Error> {
+        to_absolute(self, len)
+    }
+}
+
+impl TextRange for RangeFrom<usize> {
+    fn to_absolute(&self, len: usize) -> Result<(usize, usize), Error> {
+        to_absolute(self, len)
+    }
+}
+
+impl TextRange for RangeTo<usize> {
+    fn to_absolute(&self, len: usize) -> Result<(usize, usize), Error> {
+        to_absolute(self, len)
+    }
+}
+
+impl TextRange for RangeInclusive<usize> {
+    fn to_absolute(&self, len: usize) -> Result<(usize, usize), Error> {
+        to_absolute(self, len)
+    }
+}
+
+impl TextRange for RangeToInclusive<usize> {
+    fn to_absolute(&self, len: usize) -> Result<(usize, usize), Error> {
+        to_absolute(self, len)
+    }
+}
+
+impl TextRange for RangeFull {
+    fn to_absolute(&self, len: usize) -> Result<(usize, usize), Error> {
+        Ok((0, len))
+    }
+}
+
+impl TextRange for Range<Offset> {
+    fn to_absolute(&self, len: usize) -> Result<(usize, usize), Error> {
+        to_absolute_offset(self, len)
+    }
+}
+
+impl TextRange for RangeFrom<Offset> {
+    fn to_absolute(&self, len: usize) -> Result<(usize, usize), Error> {
+        to_absolute_offset(self, len)
+    }
+}
+
+impl TextRange for RangeTo<Offset> {
+    fn to_absolute(&self, len: usize) -> Result<(usize, usize), Error> {
+        to_absolute_offset(self, len)
+    }
+}
+
+impl TextRange for RangeInclusive<Offset> {
+    fn to_absolute(&self, len: usize) -> Result<(usize, usize), Error> {
+        to_absolute_offset(self, len)
+    }
+}
+
+impl TextRange for RangeToInclusive<Offset> {
+    fn to_absolute(&self, len: usize) -> Result<(usize, usize), Error> {
+        to_absolute_offset(self, len)
+    }
+}
+
+impl TextFile {
+    /// Resolves a character range to absolute character offsets (begin, non-inclusive end).
+    pub fn absolute_range(&self, range: impl TextRange) -> Result<(usize, usize), Error> {
+        range.to_absolute(self.positionindex.charsize)
+    }
+
+    /// Returns a text fragment for a range of unicode character points, e.g. `10..20`, `..` or `FromEnd(10)..`.
+    /// The fragment must already be in memory or an Error::NotLoaded will be returned.
+    /// Use `get_or_load_range()` instead if the fragment might not be loaded yet.
+    pub fn get_range(&self, range: impl TextRange) -> Result<&str, Error> {
+        let (begin, end) = self.absolute_range(range)?;
+        self.get_abs(begin, end)
+    }
+
+    /// Returns a text fragment for a range of unicode character points, e.g. `10..20`, `..` or `FromEnd(10)..`.
+    /// The fragment will be loaded from disk into memory if needed.
+    pub fn get_or_load_range(&mut self, range: impl TextRange) -> Result<&str, Error> {
+        let (begin, end) = self.absolute_range(range)?;
+        self.get_or_load_abs(begin, end)
+    }
+
+    /// Returns a text fragment for a range of lines (0-indexed!!), e.g. `2..5` or `FromEnd(2)..`.
+    /// The fragment must already be in memory or an Error::NotLoaded will be returned.
+    /// Use `get_or_load_lines_range()` instead if the fragment might not be loaded yet.
+    ///
+    /// This will return Error::NoLineIndex if no line index was computed.
+    pub fn get_lines_range(&self, range: impl TextRange) -> Result<&str, Error> {
+        let (beginbyte, endbyte) = self.lines_range_to_bytes(range)?;
+        self.get_byterange_unchecked(beginbyte, endbyte)
+    }
+
+    /// Returns a text fragment for a range of lines (0-indexed!!), e.g. `2..5` or `FromEnd(2)..`.
+    /// The fragment will be loaded from disk into memory if needed.
+    ///
+    /// This will return Error::NoLineIndex if no line index was computed.
+    pub fn get_or_load_lines_range(&mut self, range: impl TextRange) -> Result<&str, Error> {
+        let (beginbyte, endbyte) = self.lines_range_to_bytes(range)?;
+        self.get_or_load_bytes(beginbyte, endbyte)
+    }
+
+    /// Resolves a range of lines to a byte range
+    fn lines_range_to_bytes(&self, range: impl TextRange) -> Result<(usize, usize), Error> {
+        if self.positionindex.lines.is_empty() {
+            return Err(Error::NoLineIndex);
+        }
+        let (begin, end) = range.to_absolute(self.positionindex.lines.len())?;
+        Ok((
+            self.line_to_bytes(begin as isize)?,
+            self.line_to_bytes(end as isize)?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Offset::{FromEnd, FromStart};
+    use super::*;
+    use crate::tests::*;
+
+    #[test]
+    pub fn test023_range() {
+        let file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        assert_eq!(
+            textfile
+                .get_or_load_range(1..10)
+                .expect("text should exist"),
+            "Article 1"
+        );
+        assert_eq!(
+            textfile
+                .get_or_load_range(1..=9)
+                .expect("text should exist"),
+            "Article 1"
+        );
+        assert_eq!(
+            textfile
+                .get_or_load_range(FromEnd(7)..)
+                .expect("text should exist"),
+            "forms.\n"
+        );
+        assert_eq!(
+            textfile
+                .get_or_load_range(FromEnd(7)..FromEnd(2))
+                .expect("text should exist"),
+            "forms"
+        );
+        assert_eq!(
+            textfile.get_or_load_range(..).expect("text should exist"),
+            EXAMPLE_ASCII_TEXT
+        );
+        assert_eq!(
+            textfile
+                .get_range(FromStart(1)..FromStart(8))
+                .expect("text should exist"),
+            "Article"
+        );
+    }
+
+    #[test]
+    pub fn test023_range_empty() {
+        let file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        //unlike get_or_load(5,0), this is an empty range
+        assert_eq!(
+            textfile.get_or_load_range(5..5).expect("text should exist"),
+            ""
+        );
+        assert_eq!(
+            textfile
+                .get_or_load_range(FromEnd(0)..)
+                .expect("text should exist"),
+            ""
+        );
+        assert_eq!(textfile.absolute_range(..0).unwrap(), (0, 0));
+    }
+
+    #[test]
+    pub fn test023_range_out_of_bounds() {
+        let file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        assert!(textfile.get_or_load_range(900..1000).is_err());
+        assert!(textfile.get_or_load_range(FromEnd(1000)..).is_err());
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 20..10;
+        assert!(textfile.get_or_load_range(reversed).is_err());
+    }
+
+    #[test]
+    pub fn test023_lines_range() {
+        let file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        assert_eq!(
+            textfile
+                .get_or_load_lines_range(1..2)
+                .expect("text should exist"),
+            "Article 1\n"
+        );
+        assert_eq!(
+            textfile
+                .get_or_load_lines_range(FromEnd(2)..FromEnd(1))
+                .expect("text should exist"),
+            "No one shall be held in slavery or servitude; slavery and the slave trade shall be prohibited in all their forms.\n"
+        );
+    }
+}
@@ -0,0 +1,509 @@
+/*
+TextFrame
+  by Maarten van Gompel <proycon@anaproy.nl>
+  Digital Infrastructure, KNAW Humanities Cluster
+  licensed under the GNU General Public Licence v3
+*/
+
+//! Regular expression search by streaming through the text file, with bounded memory
+
+use regex::Regex;
+
+use crate::search::{ChunkReader, CHUNKSIZE};
+use crate::{Error, TextFile};
+
+/// Default maximum length of a match (in characters) for `RegexIter`
+pub const DEFAULT_REGEX_WINDOW: usize = 4096;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A regular expression match, all offsets are absolute character offsets (end non-inclusive)
+pub struct RegexMatch {
+    pub begin: usize,
+    pub end: usize,
+
+    /// The capture groups (excluding the implicit group for the whole match), `None` for groups that did not participate in the match
+    pub groups: Vec<Option<(usize, usize)>>,
+
+    /// The line (0-indexed!!) the match is on, only set when searching by lines
+    pub line: Option<usize>,
+}
+
+/// Iterator over the matches of a regular expression in a text file, see `TextFile::regex_iter()`
+pub struct RegexIter<'a> {
+    textfile: &'a TextFile,
+    regex: Regex,
+
+    /// Character offset from which to start searching
+    start: usize,
+
+    /// Maximum number of matches to return
+    limit: Option<usize>,
+
+    /// Maximum length of a match in characters
+    window: usize,
+
+    /// Search each line separately
+    by_lines: bool,
+
+    /// Size of the chunks to read from disk
+    chunksize: usize,
+
+    count: usize,
+
+    /// Initialised on the first call to `next()`
+    reader: Option<ChunkReader>,
+
+    /// Byte index from where to search next, relative to the buffer (or to the current line when searching by lines).
+    /// Unless the buffer starts at the very beginning of the text, this is always preceded by at least one character in the buffer,
+    /// so the regular expression sees the real context for look-behind assertions like `\b`, `^` and `\A`.
+    searchfrom: usize,
+
+    /// Byte index where the current line starts (relative to the buffer), only used when searching by lines
+    linestart: usize,
+
+    /// The current line, only used when searching by lines
+    line: usize,
+
+    /// Absolute byte offset where the last match ended
+    lastend: Option<usize>,
+
+    done: bool,
+}
+
+impl<'a> RegexIter<'a> {
+    /// Start searching at the given character offset (absolute)
+    pub fn starting_at(mut self, charpos: usize) -> Self {
+        self.start = charpos;
+        self
+    }
+
+    /// Return at most this many matches
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Set the maximum length of a match in characters (defaults to `DEFAULT_REGEX_WINDOW`), memory usage is bounded by this
+    /// window plus the chunk size. Matches that would be longer may be cut short or missed.
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.window = window.max(1);
+        self
+    }
+
+    /// Search each line separately, matches never span multiple lines and `^` and `$` match at the begin and end of each line.
+    /// The line each match is on is reported. This requires a line index, and a line must fit in memory.
+    pub fn by_lines(mut self, value: bool) -> Self {
+        self.by_lines = value;
+        self
+    }
+
+    /// Opens the reader at the start position
+    fn init(&mut self) -> Result<(), Error> {
+        if self.start > self.textfile.len() {
+            return Err(Error::OutOfBoundsError {
+                begin: self.start as isize,
+                end: 0,
+            });
+        }
+        let startbyte = self.textfile.chars_to_bytes(self.start)?;
+        let beginbyte = if self.by_lines {
+            self.line = self.textfile.byte_to_line(startbyte)?;
+            let linebyte = self.textfile.line_to_bytes(self.line as isize)?;
+            self.searchfrom = startbyte - linebyte;
+            linebyte
+        } else if self.start > 0 {
+            //start a character earlier, as context
+            let beginbyte = self.textfile.chars_to_bytes(self.start - 1)?;
+            self.searchfrom = startbyte - beginbyte;
+            beginbyte
+        } else {
+            startbyte
+        };
+        self.reader = Some(self.textfile.chunk_reader(beginbyte, self.chunksize)?);
+        Ok(())
+    }
+
+    /// Converts a match (byte offsets relative to the haystack that starts at the given absolute byte offset) to a `RegexMatch`
+    fn to_match(
+        &self,
+        haystack: &str,
+        offset: usize,
+        matchstart: usize,
+        searchfrom: usize,
+        line: Option<usize>,
+    ) -> Result<RegexMatch, Error> {
+        let mut groups = Vec::new();
+        let (begin, end) = if self.regex.captures_len() > 1 {
+            let captures = self
+                .regex
+                .captures_at(haystack, searchfrom)
+                .expect("match must exist");
+            for group in captures.iter().skip(1) {
+                groups.push(match group {
+                    Some(group) => Some((
+                        self.textfile.bytes_to_chars(offset + group.start())?,
+                        self.textfile.bytes_to_chars(offset + group.end())?,
+                    )),
+                    None => None,
+                });
+            }
+            let m = captures.get(0).expect("group 0 always exists");
+            (m.start(), m.end())
+        } else {
+            let m = self
+                .regex
+                .find_at(haystack, searchfrom)
+                .expect("match must exist");
+            (m.start(), m.end())
+        };
+        debug_assert_eq!(begin, matchstart);
+        Ok(RegexMatch {
+            begin: self.textfile.bytes_to_chars(offset + begin)?,
+            end: self.textfile.bytes_to_chars(offset + end)?,
+            groups,
+            line,
+        })
+    }
+
+    fn next_in_window(&mut self) -> Result<Option<RegexMatch>, Error> {
+        // window in bytes, a character takes up to four bytes
+        let window = self.window * 4;
+        loop {
+            let reader = self.reader.as_ref().expect("reader initialised");
+            let buffer = reader.buffer();
+            let offset = reader.offset();
+            let done = reader.is_done();
+            let found = if self.searchfrom <= buffer.len() {
+                self.regex
+                    .find_at(buffer, self.searchfrom)
+                    .map(|m| (m.start(), m.end()))
+            } else {
+                None
+            };
+            if let Some((start, end)) = found {
+                // only accept a match if more text could not have changed it
+                if done || (start + window <= buffer.len() && end < buffer.len()) {
+                    if start == end && self.lastend == Some(offset + end) {
+                        //no empty match directly after the previous match
+                        self.searchfrom = end + next_char_len(buffer, end);
+                        continue;
+                    }
+                    let result = self.to_match(buffer, offset, start, self.searchfrom, None)?;
+                    self.searchfrom = if start == end {
+                        end + next_char_len(buffer, end)
+                    } else {
+                        end
+                    };
+                    self.lastend = Some(offset + end);
+                    return Ok(Some(result));
+                }
+            } else if done {
+                return Ok(None);
+            }
+            //no match starts before this, so only keep what a match in the next chunk could span
+            let mut keep_from = if found.is_some() {
+                self.searchfrom
+            } else {
+                self.searchfrom.max(buffer.len().saturating_sub(window))
+            }
+            .min(buffer.len());
+            while !buffer.is_char_boundary(keep_from) {
+                keep_from -= 1;
+            }
+            //keep the character before as context for look-behind assertions
+            let context = buffer[..keep_from]
+                .chars()
+                .next_back()
+                .map_or(0, |c| c.len_utf8());
+            let reader = self.reader.as_mut().expect("reader initialised");
+            let discarded = reader.discard(keep_from - context);
+            self.searchfrom = self.searchfrom.max(keep_from) - discarded;
+            reader.read_chunk()?;
+        }
+    }
+
+    fn next_in_lines(&mut self) -> Result<Option<RegexMatch>, Error> {
+        loop {
+            let reader = self.reader.as_ref().expect("reader initialised");
+            let buffer = reader.buffer();
+            let done = reader.is_done();
+            let lineend = match buffer[self.linestart..].find('\n') {
+                Some(index) => self.linestart + index,
+                None if done => buffer.len(),
+                None => {
+                    let reader = self.reader.as_mut().expect("reader initialised");
+                    self.linestart -= reader.discard(self.linestart);
+                    reader.read_chunk()?;
+                    continue;
+                }
+            };
+            let offset = reader.offset() + self.linestart;
+            let haystack = &buffer[self.linestart..lineend];
+            if self.searchfrom <= haystack.len() {
+                if let Some(m) = self.regex.find_at(haystack, self.searchfrom) {
+                    let (start, end) = (m.start(), m.end());
+                    if !(start == end && self.lastend == Some(offset + end)) {
+                        let result = self.to_match(
+                            haystack,
+                            offset,
+                            start,
+                            self.searchfrom,
+                            Some(self.line),
+                        )?;
+                        self.searchfrom = if start == end {
+                            end + next_char_len(haystack, end)
+                        } else {
+                            end
+                        };
+                        self.lastend = Some(offset + end);
+                        return Ok(Some(result));
+                    }
+                    self.searchfrom = end + next_char_len(haystack, end);
+                    continue;
+                }
+            }
+            if lineend == buffer.len() {
+                //last line done
+                return Ok(None);
+            }
+            self.linestart = lineend + 1;
+            self.searchfrom = 0;
+            self.line += 1;
+        }
+    }
+}
+
+/// Returns the length in bytes of the character at the given index, or 1 at the end of the text
+fn next_char_len(text: &str, index: usize) -> usize {
+    text[index..]
+        .chars()
+        .next()
+        .map(|c| c.len_utf8())
+        .unwrap_or(1)
+}
+
+impl Iterator for RegexIter<'_> {
+    type Item = Result<RegexMatch, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.limit.is_some_and(|limit| self.count >= limit) {
+            return None;
+        }
+        if self.reader.is_none() {
+            if let Err(e) = self.init() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+        let result = if self.by_lines {
+            self.next_in_lines()
+        } else {
+            self.next_in_window()
+        };
+        match result {
+            Ok(Some(result)) => {
+                self.count += 1;
+                Some(Ok(result))
+            }
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl TextFile {
+    /// Returns an iterator over all non-overlapping matches of a regular expression, with absolute character offsets.
+    /// The text file is streamed through in bounded chunks rather than loaded into memory, matches may span multiple lines
+    /// (up to a maximum length, see `RegexIter::with_window()`), or can be restricted to single lines with `RegexIter::by_lines()`.
+    /// Use `starting_at()` and `limit()` on the iterator to restrict the search.
+    pub fn regex_iter(&self, regex: &Regex) -> RegexIter<'_> {
+        RegexIter {
+            textfile: self,
+            regex: regex.clone(),
+            start: 0,
+            limit: None,
+            window: DEFAULT_REGEX_WINDOW,
+            by_lines: false,
+            chunksize: CHUNKSIZE,
+            count: 0,
+            reader: None,
+            searchfrom: 0,
+            linestart: 0,
+            line: 0,
+            lastend: None,
+            done: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use std::io::Write;
+
+    #[test]
+    pub fn test031_regex_iter() {
+        let file = setup_ascii();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let regex = Regex::new(r"Article (\d)").unwrap();
+        let found: Vec<RegexMatch> = textfile
+            .regex_iter(&regex)
+            .map(|x| x.expect("match"))
+            .collect();
+        assert_eq!(found.len(), 4);
+        assert_eq!(found[0].begin, 1);
+        assert_eq!(found[0].end, 10);
+        assert_eq!(found[0].groups, vec![Some((9, 10))]);
+        assert_eq!(found[0].line, None);
+        let found: Vec<RegexMatch> = textfile
+            .regex_iter(&regex)
+            .starting_at(2)
+            .limit(1)
+            .map(|x| x.expect("match"))
+            .collect();
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            &EXAMPLE_ASCII_TEXT[found[0].begin..found[0].end],
+            "Article 2"
+        );
+    }
+
+    #[test]
+    pub fn test031_regex_across_lines() {
+        let file = setup_ascii();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let regex = Regex::new(r"\d\n\nAll").unwrap();
+        let found: Vec<RegexMatch> = textfile
+            .regex_iter(&regex)
+            .map(|x| x.expect("match"))
+            .collect();
+        assert_eq!(found.len(), 1);
+        assert!(textfile.regex_iter(&regex).by_lines(true).next().is_none());
+    }
+
+    #[test]
+    pub fn test031_regex_by_lines() {
+        let file = setup_ascii();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let regex = Regex::new(r"^Article (\d)$").unwrap();
+        let found: Vec<RegexMatch> = textfile
+            .regex_iter(&regex)
+            .by_lines(true)
+            .map(|x| x.expect("match"))
+            .collect();
+        assert_eq!(found.len(), 4);
+        assert_eq!(found[0].line, Some(1));
+        assert_eq!(found[1].line, Some(5));
+        assert_eq!(
+            found[3].groups,
+            vec![Some((found[3].end - 1, found[3].end))]
+        );
+        let found: Vec<RegexMatch> = textfile
+            .regex_iter(&regex)
+            .by_lines(true)
+            .starting_at(2)
+            .map(|x| x.expect("match"))
+            .collect();
+        assert_eq!(found.len(), 3);
+    }
+
+    #[test]
+    pub fn test031_regex_unicode_and_empty() {
+        let file = setup_unicode();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let regex = Regex::new(r"第.条").unwrap();
+        let found: Vec<RegexMatch> = textfile
+            .regex_iter(&regex)
+            .map(|x| x.expect("match"))
+            .collect();
+        assert!(!found.is_empty());
+        for m in found {
+            let text = textfile
+                .get_or_load(m.begin as isize, m.end as isize)
+                .expect("text should exist");
+            assert!(regex.is_match(text));
+        }
+        //empty matches do not loop forever
+        let regex = Regex::new(r"x*").unwrap();
+        assert_eq!(textfile.regex_iter(&regex).count(), textfile.len() + 1);
+    }
+
+    #[test]
+    pub fn test031_regex_anchors_across_chunks() {
+        let text = "Article 1\nSee Article 2.\nArticle 3 x\n".repeat(3);
+        let mut file = tempfile::NamedTempFile::new().expect("temp file");
+        write!(file, "{}", text).expect("write must work");
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        for pattern in [
+            r"\AArticle \d",
+            r"^Article \d",
+            r"(?m)^Article \d",
+            r"(?m)\d$",
+            r"\bArt",
+            r"\Bicle",
+        ] {
+            let regex = Regex::new(pattern).unwrap();
+            for start in [0, 1, 10, 11, 12, 40] {
+                let expected: Vec<(usize, usize)> = regex
+                    .find_iter(&text)
+                    .map(|m| (m.start(), m.end()))
+                    .filter(|(begin, _)| *begin >= start)
+                    .collect();
+                for chunksize in [4, 7, 64] {
+                    let mut iter = textfile
+                        .regex_iter(&regex)
+                        .starting_at(start)
+                        .with_window(12);
+                    iter.chunksize = chunksize;
+                    let found: Vec<(usize, usize)> = iter
+                        .map(|x| x.expect("match"))
+                        .map(|m| (m.begin, m.end))
+                        .collect();
+                    assert_eq!(
+                        found, expected,
+                        "pattern {} start {} chunksize {}",
+                        pattern, start, chunksize
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn test031_regex_small_chunks() {
+        let file = setup_ascii();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let regex = Regex::new(r"\b[Ss]\w+").unwrap();
+        let expected: Vec<(usize, usize)> = regex
+            .find_iter(EXAMPLE_ASCII_TEXT)
+            .map(|m| (m.start(), m.end()))
+            .collect();
+        for by_lines in [false, true] {
+            for chunksize in [4, 7, 64] {
+                let mut iter = textfile
+                    .regex_iter(&regex)
+                    .by_lines(by_lines)
+                    .with_window(20);
+                iter.chunksize = chunksize;
+                let found: Vec<(usize, usize)> = iter
+                    .map(|x| x.expect("match"))
+                    .map(|m| (m.begin, m.end))
+                    .collect();
+                assert_eq!(found, expected, "chunksize {}", chunksize);
+            }
+        }
+    }
+}
@@ -0,0 +1,417 @@
+/*
+TextFrame
+  by Maarten van Gompel <proycon@anaproy.nl>
+  Digital Infrastructure, KNAW Humanities Cluster
+  licensed under the GNU General Public Licence v3
+*/
+
+//! Searching by streaming through the text file in bounded chunks, without loading it into memory entirely
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::{Error, TextFile};
+
+/// Default size of the chunks that are read from disk when streaming through a text file
+pub(crate) const CHUNKSIZE: usize = 1 << 20;
+
+/// Reads a text file in chunks into a buffer, the buffer always holds valid UTF-8
+pub(crate) struct ChunkReader {
+    file: File,
+
+    /// Absolute byte offset of the start of the buffer
+    offset: usize,
+
+    buffer: String,
+
+    /// Incomplete UTF-8 sequence at the end of the last read chunk
+    pending: Vec<u8>,
+
+    /// Absolute byte offset of the next byte to read from disk
+    readpos: usize,
+
+    /// Absolute byte offset at which to stop reading
+    endbyte: usize,
+
+    chunksize: usize,
+}
+
+impl ChunkReader {
+    /// Opens a file for reading chunks from `beginbyte` to `endbyte`, the offsets must be at character boundaries
+    pub(crate) fn new(
+        path: &Path,
+        beginbyte: usize,
+        endbyte: usize,
+        chunksize: usize,
+    ) -> Result<Self, Error> {
+        let mut file = File::open(path).map_err(Error::IOError)?;
+        file.seek(SeekFrom::Start(beginbyte as u64))
+            .map_err(Error::IOError)?;
+        Ok(Self {
+            file,
+            offset: beginbyte,
+            buffer: String::new(),
+            pending: Vec::new(),
+            readpos: beginbyte,
+            endbyte,
+            chunksize: chunksize.max(4),
+        })
+    }
+
+    /// The buffer, holding the text read so far (minus anything discarded)
+    pub(crate) fn buffer(&self) -> &str {
+        self.buffer.as_str()
+    }
+
+    /// Absolute byte offset of the start of the buffer
+    pub(crate) fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns true if everything up to the end byte has been read
+    pub(crate) fn is_done(&self) -> bool {
+        self.readpos >= self.endbyte
+    }
+
+    /// Reads the next chunk from disk and appends it to the buffer, returns false if there was nothing left to read
+    pub(crate) fn read_chunk(&mut self) -> Result<bool, Error> {
+        if self.is_done() {
+            return Ok(false);
+        }
+        let size = self.chunksize.min(self.endbyte - self.readpos);
+        let mut bytes = std::mem::take(&mut self.pending);
+        let begin = bytes.len();
+        bytes.resize(begin + size, 0);
+        self.file
+            .read_exact(&mut bytes[begin..])
+            .map_err(Error::IOError)?;
+        self.readpos += size;
+        let valid = match std::str::from_utf8(&bytes) {
+            Ok(_) => bytes.len(),
+            //an incomplete sequence at the end, completed by the next chunk
+            Err(e) if e.error_len().is_none() && !self.is_done() => e.valid_up_to(),
+            Err(_) => {
+                return Err(Error::Utf8Error(
+                    String::from_utf8(bytes).expect_err("must be invalid"),
+                ))
+            }
+        };
+        self.pending = bytes.split_off(valid);
+        self.buffer
+            .push_str(std::str::from_utf8(&bytes).expect("validated"));
+        Ok(true)
+    }
+
+    /// Discards the start of the buffer up to the given byte index (relative to the buffer), rounded down to a character boundary.
+    /// Returns the number of bytes actually discarded.
+    pub(crate) fn discard(&mut self, mut index: usize) -> usize {
+        index = index.min(self.buffer.len());
+        while !self.buffer.is_char_boundary(index) {
+            index -= 1;
+        }
+        self.buffer.drain(..index);
+        self.offset += index;
+        index
+    }
+}
+
+/// Iterator over the absolute byte offsets at which a pattern occurs, streaming through the text file chunk by chunk
+pub(crate) struct ByteMatches {
+    reader: ChunkReader,
+    pattern: String,
+
+    /// Byte index in the buffer from where to search next
+    searchfrom: usize,
+
+    /// Whether overlapping matches are returned
+    overlapping: bool,
+}
+
+impl ByteMatches {
+    pub(crate) fn new(reader: ChunkReader, pattern: &str, overlapping: bool) -> Self {
+        Self {
+            reader,
+            pattern: pattern.to_string(),
+            searchfrom: 0,
+            overlapping,
+        }
+    }
+}
+
+impl Iterator for ByteMatches {
+    type Item = Result<usize, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pattern.is_empty() {
+            return None;
+        }
+        loop {
+            if let Some(index) = self.reader.buffer()[self.searchfrom..].find(&self.pattern) {
+                let index = self.searchfrom + index;
+                self.searchfrom = if self.overlapping {
+                    index
+                        + self
+                            .pattern
+                            .chars()
+                            .next()
+                            .expect("pattern is not empty")
+                            .len_utf8()
+                } else {
+                    index + self.pattern.len()
+                };
+                return Some(Ok(self.reader.offset() + index));
+            }
+            //no (further) match in the buffer, only a match starting in the last pattern.len() - 1 bytes could still be completed by the next chunk
+            let keep_from = self.searchfrom.max(
+                self.reader
+                    .buffer()
+                    .len()
+                    .saturating_sub(self.pattern.len() - 1),
+            );
+            let discarded = self.reader.discard(keep_from);
+            self.searchfrom = self.searchfrom.saturating_sub(discarded);
+            match self.reader.read_chunk() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Iterator over the occurrences of a substring in a text file, see `TextFile::find_iter()`
+pub struct FindIter<'a> {
+    textfile: &'a TextFile,
+    pattern: String,
+
+    /// Length of the pattern in characters
+    patternlen: usize,
+
+    /// Character offset from which to start searching
+    start: usize,
+
+    /// Maximum number of matches to return
+    limit: Option<usize>,
+
+    count: usize,
+
+    /// Initialised on the first call to `next()`
+    matches: Option<ByteMatches>,
+
+    done: bool,
+}
+
+impl<'a> FindIter<'a> {
+    /// Start searching at the given character offset (absolute)
+    pub fn starting_at(mut self, charpos: usize) -> Self {
+        self.start = charpos;
+        self
+    }
+
+    /// Return at most this many matches
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+}
+
+impl Iterator for FindIter<'_> {
+    type Item = Result<(usize, usize), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.limit.is_some_and(|limit| self.count >= limit) {
+            return None;
+        }
+        if self.matches.is_none() {
+            if self.start > self.textfile.len() {
+                self.done = true;
+                return Some(Err(Error::OutOfBoundsError {
+                    begin: self.start as isize,
+                    end: 0,
+                }));
+            }
+            let reader = self
+                .textfile
+                .chars_to_bytes(self.start)
+                .and_then(|beginbyte| self.textfile.chunk_reader(beginbyte, CHUNKSIZE));
+            match reader {
+                Ok(reader) => self.matches = Some(ByteMatches::new(reader, &self.pattern, false)),
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        let result = match self.matches.as_mut()?.next()? {
+            Ok(beginbyte) => self
+                .textfile
+                .bytes_to_chars(beginbyte)
+                .map(|begin| (begin, begin + self.patternlen)),
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            self.done = true;
+        }
+        self.count += 1;
+        Some(result)
+    }
+}
+
+impl TextFile {
+    /// Opens a reader that streams the text file from the given byte offset to the end, in chunks.
+    /// Fails with `Error::SourceModified` if the file is being watched and has pending modifications.
+    pub(crate) fn chunk_reader(
+        &self,
+        beginbyte: usize,
+        chunksize: usize,
+    ) -> Result<ChunkReader, Error> {
+        #[cfg(feature = "watch")]
+        self.check_watched_pending()?;
+        ChunkReader::new(
+            self.path.as_path(),
+            beginbyte,
+            self.positionindex.bytesize,
+            chunksize,
+        )
+    }
+
+    /// Returns an iterator over all non-overlapping occurrences of a substring, as absolute character ranges (begin, non-inclusive end).
+    /// The text file is streamed through in bounded chunks rather than loaded into memory, matches across chunk borders are found too.
+    /// Use `starting_at()` and `limit()` on the iterator to restrict the search.
+    pub fn find_iter(&self, pattern: &str) -> FindIter<'_> {
+        FindIter {
+            textfile: self,
+            pattern: pattern.to_string(),
+            patternlen: pattern.chars().count(),
+            start: 0,
+            limit: None,
+            count: 0,
+            matches: None,
+            done: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use std::io::Write;
+
+    #[test]
+    pub fn test029_chunk_reader() {
+        let file = setup_unicode();
+        for chunksize in [4, 5, 7, 100, CHUNKSIZE] {
+            let mut reader =
+                ChunkReader::new(file.path(), 0, EXAMPLE_UNICODE_TEXT.len(), chunksize)
+                    .expect("reader must open");
+            while reader.read_chunk().expect("read must work") {}
+            assert_eq!(reader.buffer(), EXAMPLE_UNICODE_TEXT);
+            assert!(reader.is_done());
+        }
+    }
+
+    #[test]
+    pub fn test029_byte_matches_across_chunks() {
+        let file = setup_ascii();
+        let expected: Vec<usize> = EXAMPLE_ASCII_TEXT
+            .match_indices("Article")
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(expected.len(), 4);
+        for chunksize in [4, 5, 7, 100, CHUNKSIZE] {
+            let reader = ChunkReader::new(file.path(), 0, EXAMPLE_ASCII_TEXT.len(), chunksize)
+                .expect("reader must open");
+            let found: Vec<usize> = ByteMatches::new(reader, "Article", false)
+                .map(|x| x.expect("match"))
+                .collect();
+            assert_eq!(found, expected, "chunksize {}", chunksize);
+        }
+    }
+
+    #[test]
+    pub fn test029_byte_matches_multibyte() {
+        let file = setup_unicode();
+        let reader = ChunkReader::new(file.path(), 0, EXAMPLE_UNICODE_TEXT.len(), 5)
+            .expect("reader must open");
+        let pattern = "条";
+        let found: Vec<usize> = ByteMatches::new(reader, pattern, false)
+            .map(|x| x.expect("match"))
+            .collect();
+        let expected: Vec<usize> = EXAMPLE_UNICODE_TEXT
+            .match_indices(pattern)
+            .map(|(i, _)| i)
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    pub fn test029_byte_matches_overlapping() {
+        let mut file = tempfile::NamedTempFile::new().expect("temp file");
+        write!(file, "aaaaa").expect("write must work");
+        let reader = ChunkReader::new(file.path(), 0, 5, 4).expect("reader must open");
+        let found: Vec<usize> = ByteMatches::new(reader, "aa", true)
+            .map(|x| x.expect("match"))
+            .collect();
+        assert_eq!(found, vec![0, 1, 2, 3]);
+        let reader = ChunkReader::new(file.path(), 0, 5, 4).expect("reader must open");
+        let found: Vec<usize> = ByteMatches::new(reader, "aa", false)
+            .map(|x| x.expect("match"))
+            .collect();
+        assert_eq!(found, vec![0, 2]);
+    }
+
+    #[test]
+    pub fn test030_find_iter() {
+        let file = setup_ascii();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let found: Vec<(usize, usize)> = textfile
+            .find_iter("Article")
+            .map(|x| x.expect("match"))
+            .collect();
+        assert_eq!(found.len(), 4);
+        assert_eq!(found[0], (1, 8));
+        //ascii text, so character offsets equal byte offsets
+        for (begin, end) in found.iter() {
+            assert_eq!(&EXAMPLE_ASCII_TEXT[*begin..*end], "Article");
+        }
+        let found2: Vec<(usize, usize)> = textfile
+            .find_iter("Article")
+            .starting_at(2)
+            .limit(2)
+            .map(|x| x.expect("match"))
+            .collect();
+        assert_eq!(found2, found[1..3]);
+        assert_eq!(textfile.find_iter("Article 5").count(), 0);
+        assert_eq!(textfile.find_iter("").count(), 0);
+        assert!(textfile
+            .find_iter("Article")
+            .starting_at(10000)
+            .next()
+            .expect("error expected")
+            .is_err());
+    }
+
+    #[test]
+    pub fn test030_find_iter_unicode() {
+        let file = setup_unicode();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let found: Vec<(usize, usize)> = textfile
+            .find_iter("人人")
+            .map(|x| x.expect("match"))
+            .collect();
+        assert!(!found.is_empty());
+        for (begin, end) in found {
+            assert_eq!(
+                textfile
+                    .get_or_load(begin as isize, end as isize)
+                    .expect("text should exist"),
+                "人人"
+            );
+        }
+    }
+}
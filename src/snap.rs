@@ -0,0 +1,249 @@
+/*
+TextFrame
+  by Maarten van Gompel <proycon@anaproy.nl>
+  Digital Infrastructure, KNAW Humanities Cluster
+  licensed under the GNU General Public Licence v3
+*/
+
+//! Snapping offsets to character, word, sentence or line boundaries, for turning imprecise external offsets into clean selections
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{Error, TextFile};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The kind of boundary to snap to
+pub enum Boundary {
+    /// Word boundaries as defined by Unicode Standard Annex #29 (whitespace and punctuation between words form their own segments)
+    Word,
+
+    /// Sentence boundaries as defined by Unicode Standard Annex #29, note that a newline always ends a sentence there
+    Sentence,
+
+    /// Line boundaries, a line includes its trailing newline
+    Line,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// The direction in which to snap a range
+pub enum SnapDirection {
+    /// Expand the range so it fully covers all units it touches
+    #[default]
+    Outward,
+
+    /// Shrink the range so it only covers units that are completely inside it
+    Inward,
+}
+
+impl TextFile {
+    /// Rounds a byte position down to the nearest character boundary (a no-op if it is already at one).
+    pub fn floor_char_boundary(&self, bytepos: usize) -> Result<usize, Error> {
+        let (floor, _) = self.char_boundaries(bytepos)?;
+        Ok(floor)
+    }
+
+    /// Rounds a byte position up to the nearest character boundary (a no-op if it is already at one).
+    pub fn ceil_char_boundary(&self, bytepos: usize) -> Result<usize, Error> {
+        let (floor, size) = self.char_boundaries(bytepos)?;
+        if floor == bytepos {
+            Ok(floor)
+        } else {
+            Ok(floor + size)
+        }
+    }
+
+    /// Returns the character boundary at or before the byte position, and the size in bytes of the character there
+    fn char_boundaries(&self, bytepos: usize) -> Result<(usize, usize), Error> {
+        if bytepos > self.positionindex.bytesize {
+            return Err(Error::OutOfBoundsError {
+                begin: bytepos as isize,
+                end: 0,
+            });
+        }
+        let positions = &self.positionindex.positions;
+        match positions.binary_search_by_bytepos(bytepos) {
+            Ok(index) => Ok((bytepos, positions.size(index).unwrap() as usize)),
+            Err(0) => Err(Error::EmptyText),
+            Err(index) => {
+                let prev_byte = positions.bytepos(index - 1).unwrap();
+                let size = positions.size(index - 1).unwrap() as usize;
+                Ok((prev_byte + (bytepos - prev_byte) / size * size, size))
+            }
+        }
+    }
+
+    /// Snaps a character range (absolute offsets, end non-inclusive) to word, sentence or line boundaries,
+    /// either expanding it outward or shrinking it inward. Text will be loaded from disk into memory if needed (only the lines at begin and end).
+    /// When shrinking inward and no complete unit fits in the range, an empty range at `begin` is returned.
+    ///
+    /// This will return an `Error::NoLineIndex` if no line index was computed/loaded.
+    pub fn snap_to_boundaries(
+        &mut self,
+        begin: usize,
+        end: usize,
+        boundary: Boundary,
+        direction: SnapDirection,
+    ) -> Result<(usize, usize), Error> {
+        self.check_order(begin, end)?;
+        if end > self.positionindex.charsize {
+            return Err(Error::OutOfBoundsError {
+                begin: begin as isize,
+                end: end as isize,
+            });
+        }
+        let beginboundaries = self.boundaries_around(begin, boundary)?;
+        let endboundaries = self.boundaries_around(end, boundary)?;
+        //the boundaries always include the begin and end of the line, so floor and ceil always exist
+        let floor = |boundaries: &[usize], charpos: usize| {
+            *boundaries.iter().rev().find(|b| **b <= charpos).unwrap()
+        };
+        let ceil = |boundaries: &[usize], charpos: usize| {
+            *boundaries.iter().find(|b| **b >= charpos).unwrap()
+        };
+        match direction {
+            SnapDirection::Outward => {
+                Ok((floor(&beginboundaries, begin), ceil(&endboundaries, end)))
+            }
+            SnapDirection::Inward => {
+                let newbegin = ceil(&beginboundaries, begin);
+                let newend = floor(&endboundaries, end);
+                if newbegin > newend {
+                    Ok((begin, begin))
+                } else {
+                    Ok((newbegin, newend))
+                }
+            }
+        }
+    }
+
+    /// Returns all boundaries (absolute character offsets, ascending) in the line the character position is on,
+    /// including the begin and end of the line. No boundary crosses a line, so that is all the context we need.
+    fn boundaries_around(
+        &mut self,
+        charpos: usize,
+        boundary: Boundary,
+    ) -> Result<Vec<usize>, Error> {
+        let line = self.char_to_line(charpos)?;
+        let beginbyte = self
+            .positionindex
+            .lines
+            .get(line)
+            .expect("line should exist");
+        let endbyte = self
+            .positionindex
+            .lines
+            .get(line + 1)
+            .unwrap_or(self.positionindex.bytesize);
+        if beginbyte == endbyte {
+            return Ok(vec![charpos]);
+        }
+        let linebegin = self.bytes_to_chars(beginbyte)?;
+        let lineend = self.bytes_to_chars(endbyte)?;
+        if boundary == Boundary::Line {
+            return Ok(vec![linebegin, lineend]);
+        }
+        let text = self.get_or_load_bytes(beginbyte, endbyte)?;
+        let mut boundaries = vec![linebegin];
+        let mut charpos = linebegin;
+        let segments: Box<dyn Iterator<Item = &str>> = match boundary {
+            Boundary::Word => Box::new(text.split_word_bounds()),
+            _ => Box::new(text.split_sentence_bounds()),
+        };
+        for segment in segments {
+            charpos += segment.chars().count();
+            boundaries.push(charpos);
+        }
+        Ok(boundaries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+
+    #[test]
+    pub fn test026_char_boundary() {
+        let file = setup_unicode();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        //"\n第一条": 第 occupies bytes 1..4
+        assert_eq!(textfile.floor_char_boundary(2).unwrap(), 1);
+        assert_eq!(textfile.ceil_char_boundary(2).unwrap(), 4);
+        assert_eq!(textfile.floor_char_boundary(4).unwrap(), 4);
+        assert_eq!(textfile.ceil_char_boundary(4).unwrap(), 4);
+        assert_eq!(textfile.ceil_char_boundary(0).unwrap(), 0);
+        assert!(textfile.floor_char_boundary(10000).is_err());
+    }
+
+    #[test]
+    pub fn test026_snap_words() {
+        let file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        //"Article 1": "rticl" -> "Article"
+        assert_eq!(
+            textfile
+                .snap_to_boundaries(2, 7, Boundary::Word, SnapDirection::Outward)
+                .unwrap(),
+            (1, 8)
+        );
+        assert_eq!(
+            textfile
+                .snap_to_boundaries(2, 7, Boundary::Word, SnapDirection::Inward)
+                .unwrap(),
+            (2, 2)
+        );
+        //"rticle 1" -> " 1"
+        assert_eq!(
+            textfile
+                .snap_to_boundaries(2, 10, Boundary::Word, SnapDirection::Inward)
+                .unwrap(),
+            (8, 10)
+        );
+        //already at boundaries
+        assert_eq!(
+            textfile
+                .snap_to_boundaries(1, 8, Boundary::Word, SnapDirection::Outward)
+                .unwrap(),
+            (1, 8)
+        );
+    }
+
+    #[test]
+    pub fn test026_snap_lines_and_sentences() {
+        let file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let (begin, end) = textfile
+            .snap_to_boundaries(3, 5, Boundary::Line, SnapDirection::Outward)
+            .unwrap();
+        assert_eq!(
+            textfile.get_or_load(begin as isize, end as isize).unwrap(),
+            "Article 1\n"
+        );
+        assert_eq!(
+            textfile
+                .snap_to_boundaries(3, 5, Boundary::Line, SnapDirection::Inward)
+                .unwrap(),
+            (3, 3)
+        );
+        assert!(textfile
+            .snap_to_boundaries(5, 3, Boundary::Line, SnapDirection::Outward)
+            .is_err());
+        //first sentence of the first paragraph
+        let (linebegin, _) = textfile.line_chars(3).unwrap();
+        let (begin, end) = textfile
+            .snap_to_boundaries(
+                linebegin + 5,
+                linebegin + 6,
+                Boundary::Sentence,
+                SnapDirection::Outward,
+            )
+            .unwrap();
+        assert_eq!(
+            textfile.get(begin as isize, end as isize).unwrap(),
+            "All human beings are born free and equal in dignity and rights. "
+        );
+    }
+}
@@ -0,0 +1,361 @@
+/*
+TextFrame
+  by Maarten van Gompel <proycon@anaproy.nl>
+  Digital Infrastructure, KNAW Humanities Cluster
+  licensed under the GNU General Public Licence v3
+*/
+
+//! Rendering of text snippets with highlighted ranges, for terminal output (ANSI) or HTML previews
+
+use std::fmt::Write;
+
+use crate::{ContextUnit, Error, TextFile};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// The output format of a snippet
+pub enum SnippetFormat {
+    /// Text with ANSI escape sequences for terminals, highlights are shown with a coloured background
+    #[default]
+    Ansi,
+
+    /// HTML, highlights are marked with `<mark>` and the text is escaped
+    Html,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// Determines how overlapping (or adjacent) highlights are rendered
+pub enum Overlap {
+    /// Overlapping and adjacent highlights are merged into a single one
+    #[default]
+    Merge,
+
+    /// Overlapping highlights are nested: text covered by multiple highlights is marked multiple times (HTML),
+    /// or with a different colour (ANSI). Crossing highlights are split so the markup remains properly nested.
+    Nest,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Options for rendering a snippet
+pub struct SnippetOptions {
+    format: SnippetFormat,
+    overlap: Overlap,
+    context: usize,
+    unit: ContextUnit,
+    line_numbers: bool,
+}
+
+impl Default for SnippetOptions {
+    fn default() -> Self {
+        Self {
+            format: SnippetFormat::Ansi,
+            overlap: Overlap::Merge,
+            context: 40,
+            unit: ContextUnit::Chars,
+            line_numbers: false,
+        }
+    }
+}
+
+impl SnippetOptions {
+    pub fn with_format(mut self, format: SnippetFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_overlap(mut self, overlap: Overlap) -> Self {
+        self.overlap = overlap;
+        self
+    }
+
+    /// Set the amount of context to show before the first and after the last highlight, and the unit it is expressed in (default: 40 characters)
+    pub fn with_context(mut self, context: usize, unit: ContextUnit) -> Self {
+        self.context = context;
+        self.unit = unit;
+        self
+    }
+
+    /// Prefix every line with its line number (1-indexed, as is customary for display).
+    /// This requires a line index, `Error::NoLineIndex` is returned otherwise.
+    pub fn with_line_numbers(mut self, value: bool) -> Self {
+        self.line_numbers = value;
+        self
+    }
+}
+
+const ANSI_RESET: &str = "\x1b[0m";
+const ANSI_DIM: &str = "\x1b[2m";
+const ANSI_HIGHLIGHT: &str = "\x1b[30;43m";
+const ANSI_HIGHLIGHT_NESTED: &str = "\x1b[30;41m";
+const ELLIPSIS: &str = "…";
+
+/// Writes the markup for a snippet, keeping track of the current highlight depth
+struct SnippetWriter {
+    format: SnippetFormat,
+    out: String,
+    depth: usize,
+}
+
+impl SnippetWriter {
+    /// Opens or closes highlights until the given depth is reached
+    fn set_depth(&mut self, depth: usize) {
+        match self.format {
+            SnippetFormat::Html => {
+                for _ in depth..self.depth {
+                    self.out.push_str("</mark>");
+                }
+                for _ in self.depth..depth {
+                    self.out.push_str("<mark>");
+                }
+            }
+            SnippetFormat::Ansi => {
+                //escape sequences do not nest, the style is replaced instead
+                if depth != self.depth {
+                    self.out.push_str(match depth {
+                        0 => ANSI_RESET,
+                        1 => ANSI_HIGHLIGHT,
+                        _ => ANSI_HIGHLIGHT_NESTED,
+                    });
+                }
+            }
+        }
+        self.depth = depth;
+    }
+
+    fn push_char(&mut self, c: char) {
+        match (self.format, c) {
+            (SnippetFormat::Html, '&') => self.out.push_str("&amp;"),
+            (SnippetFormat::Html, '<') => self.out.push_str("&lt;"),
+            (SnippetFormat::Html, '>') => self.out.push_str("&gt;"),
+            (SnippetFormat::Html, '"') => self.out.push_str("&quot;"),
+            (SnippetFormat::Html, '\'') => self.out.push_str("&#39;"),
+            _ => self.out.push(c),
+        }
+    }
+
+    /// Writes a line number (1-indexed), right-aligned to the given width
+    fn push_line_number(&mut self, line: usize, width: usize) {
+        match self.format {
+            SnippetFormat::Html => write!(
+                self.out,
+                "<span class=\"lineno\">{:>width$}</span> ",
+                line + 1
+            ),
+            SnippetFormat::Ansi => write!(
+                self.out,
+                "{}{:>width$} |{} ",
+                ANSI_DIM,
+                line + 1,
+                ANSI_RESET
+            ),
+        }
+        .expect("writing to a string can not fail");
+    }
+}
+
+impl TextFile {
+    /// Renders the region of the text around the given character ranges (absolute offsets, end non-inclusive) with those ranges highlighted.
+    /// The region spans from the first to the last range, plus the configured amount of context (clamped at the edges of the text file).
+    /// An ellipsis is shown where the region is cut off from the rest of the text. Highlights spanning multiple lines are closed at the end of
+    /// each line and reopened on the next, so every line is complete in itself. Returns an empty string if there are no ranges.
+    /// The region will be loaded from disk into memory if needed.
+    pub fn render_snippet(
+        &mut self,
+        highlights: &[(usize, usize)],
+        options: SnippetOptions,
+    ) -> Result<String, Error> {
+        for (begin, end) in highlights {
+            self.check_bounds(*begin, *end)?;
+        }
+        let (Some(begin), Some(end)) = (
+            highlights.iter().map(|(begin, _)| *begin).min(),
+            highlights.iter().map(|(_, end)| *end).max(),
+        ) else {
+            return Ok(String::new());
+        };
+        let len = self.len();
+        let ends_with_newline = self.ends_with_newline()?;
+        let region = self.get_or_load_with_context_abs(
+            begin,
+            end,
+            options.context,
+            options.context,
+            options.unit,
+        )?;
+        let (leftbegin, rightend) = (region.leftbegin, region.rightend);
+        let text = format!("{}{}{}", region.left, region.text, region.right);
+
+        //changes in highlight depth, per character in the region
+        let mut deltas: Vec<isize> = vec![0; rightend - leftbegin + 1];
+        let ranges = match options.overlap {
+            Overlap::Merge => merge_ranges(highlights),
+            Overlap::Nest => highlights.to_vec(),
+        };
+        for (begin, end) in ranges {
+            if begin < end {
+                deltas[begin - leftbegin] += 1;
+                deltas[end - leftbegin] -= 1;
+            }
+        }
+
+        let numbering = if options.line_numbers {
+            let firstline = self.char_to_line(leftbegin)?;
+            let lastline = self.char_to_line(rightend)?;
+            Some((firstline, (lastline + 1).to_string().len()))
+        } else {
+            None
+        };
+        let mut writer = SnippetWriter {
+            format: options.format,
+            out: String::new(),
+            depth: 0,
+        };
+        let mut line = numbering.map(|(firstline, _)| firstline).unwrap_or(0);
+        if let Some((_, width)) = numbering {
+            writer.push_line_number(line, width);
+        }
+        if leftbegin > 0 {
+            writer.out.push_str(ELLIPSIS);
+        }
+        let mut depth: isize = 0;
+        for (c, delta) in text.chars().zip(deltas.iter()) {
+            depth += delta;
+            if c == '\n' {
+                writer.set_depth(0);
+                writer.out.push('\n');
+                line += 1;
+                if let Some((_, width)) = numbering {
+                    writer.push_line_number(line, width);
+                }
+            } else {
+                writer.set_depth(depth as usize);
+                writer.push_char(c);
+            }
+        }
+        writer.set_depth(0);
+        // a final newline by itself does not count as truncated text
+        if rightend < len && !(rightend + 1 == len && ends_with_newline) {
+            writer.out.push_str(ELLIPSIS);
+        }
+        Ok(writer.out)
+    }
+}
+
+/// Merges overlapping and adjacent ranges, returns them in order
+fn merge_ranges(ranges: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = ranges
+        .iter()
+        .copied()
+        .filter(|(begin, end)| begin < end)
+        .collect();
+    ranges.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+    for (begin, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if begin <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((begin, end)),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use std::io::Write;
+
+    const EXAMPLE_CODE_TEXT: &str = "if a < b && c > d then\nreturn 'x';\n";
+
+    fn setup_code() -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().expect("temp file");
+        write!(file, "{}", EXAMPLE_CODE_TEXT).expect("write must work");
+        file
+    }
+
+    #[test]
+    pub fn test037_snippet_html() {
+        let file = setup_code();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let options = SnippetOptions::default()
+            .with_format(SnippetFormat::Html)
+            .with_context(2, ContextUnit::Chars);
+        let highlights = [(3, 8), (5, 11)];
+        assert_eq!(
+            textfile.render_snippet(&highlights, options).unwrap(),
+            "…f <mark>a &lt; b &amp;&amp;</mark> c…"
+        );
+        assert_eq!(
+            textfile
+                .render_snippet(&highlights, options.with_overlap(Overlap::Nest))
+                .unwrap(),
+            "…f <mark>a <mark>&lt; b</mark> &amp;&amp;</mark> c…"
+        );
+        //the order of the highlights does not matter
+        assert_eq!(
+            textfile
+                .render_snippet(&[(5, 11), (3, 8)], options.with_overlap(Overlap::Nest))
+                .unwrap(),
+            "…f <mark>a <mark>&lt; b</mark> &amp;&amp;</mark> c…"
+        );
+        assert_eq!(textfile.render_snippet(&[], options).unwrap(), "");
+        assert!(textfile.render_snippet(&[(3, 100)], options).is_err());
+    }
+
+    #[test]
+    pub fn test037_snippet_line_numbers() {
+        let file = setup_code();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        //a highlight across lines is closed and reopened
+        let options = SnippetOptions::default()
+            .with_format(SnippetFormat::Html)
+            .with_context(0, ContextUnit::Lines)
+            .with_line_numbers(true);
+        assert_eq!(
+            textfile.render_snippet(&[(18, 25)], options).unwrap(),
+            "<span class=\"lineno\">1</span> if a &lt; b &amp;&amp; c &gt; d <mark>then</mark>\n<span class=\"lineno\">2</span> <mark>re</mark>turn &#39;x&#39;;"
+        );
+        let options = SnippetOptions::default()
+            .with_context(0, ContextUnit::Lines)
+            .with_line_numbers(true);
+        assert_eq!(
+            textfile.render_snippet(&[(23, 29)], options).unwrap(),
+            "\x1b[2m2 |\x1b[0m …\x1b[30;43mreturn\x1b[0m 'x';"
+        );
+    }
+
+    #[test]
+    pub fn test037_snippet_ansi() {
+        let file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let begin = EXAMPLE_ASCII_TEXT.find("dignity").unwrap();
+        let options = SnippetOptions::default().with_context(3, ContextUnit::Chars);
+        assert_eq!(
+            textfile
+                .render_snippet(&[(begin, begin + 7)], options)
+                .unwrap(),
+            "…in \x1b[30;43mdignity\x1b[0m an…"
+        );
+        //nested highlights get a different colour
+        assert_eq!(
+            textfile
+                .render_snippet(
+                    &[(begin, begin + 7), (begin + 3, begin + 4)],
+                    options.with_overlap(Overlap::Nest)
+                )
+                .unwrap(),
+            "…in \x1b[30;43mdig\x1b[30;41mn\x1b[30;43mity\x1b[0m an…"
+        );
+        //lines with line numbers, aligned
+        let options = SnippetOptions::default()
+            .with_context(1, ContextUnit::Lines)
+            .with_line_numbers(true);
+        let snippet = textfile
+            .render_snippet(&[(begin, begin + 7)], options)
+            .unwrap();
+        assert!(snippet.starts_with("\x1b[2m3 |\x1b[0m …\n\x1b[2m4 |\x1b[0m All human"));
+        assert!(snippet.ends_with("\x1b[2m5 |\x1b[0m …"));
+    }
+}
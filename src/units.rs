@@ -0,0 +1,279 @@
+/*
+TextFrame
+  by Maarten van Gompel <proycon@anaproy.nl>
+  Digital Infrastructure, KNAW Humanities Cluster
+  licensed under the GNU General Public Licence v3
+*/
+
+//! Type-safe offsets, so character offsets, byte offsets and line numbers can not be mixed up accidentally.
+//! Conversions between them always go through a `TextFile`.
+
+use std::fmt;
+
+use crate::{Error, TextFile};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// An absolute offset in unicode characters (0-indexed)
+pub struct CharPos(pub usize);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// An absolute offset in UTF-8 bytes (0-indexed)
+pub struct BytePos(pub usize);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// A line number (0-indexed!!)
+pub struct LineNo(pub usize);
+
+impl fmt::Display for CharPos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::Display for BytePos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::Display for LineNo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// A range of unicode characters (end is non-inclusive)
+pub struct CharRange {
+    pub begin: CharPos,
+    pub end: CharPos,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// A range of UTF-8 bytes (end is non-inclusive)
+pub struct ByteRange {
+    pub begin: BytePos,
+    pub end: BytePos,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// A range of lines (end is non-inclusive)
+pub struct LineRange {
+    pub begin: LineNo,
+    pub end: LineNo,
+}
+
+impl CharRange {
+    pub fn new(begin: CharPos, end: CharPos) -> Self {
+        Self { begin, end }
+    }
+
+    /// Returns the length of the range in characters
+    pub fn len(&self) -> usize {
+        self.end.0.saturating_sub(self.begin.0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ByteRange {
+    pub fn new(begin: BytePos, end: BytePos) -> Self {
+        Self { begin, end }
+    }
+
+    /// Returns the length of the range in bytes
+    pub fn len(&self) -> usize {
+        self.end.0.saturating_sub(self.begin.0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl LineRange {
+    pub fn new(begin: LineNo, end: LineNo) -> Self {
+        Self { begin, end }
+    }
+
+    /// Returns the number of lines in the range
+    pub fn len(&self) -> usize {
+        self.end.0.saturating_sub(self.begin.0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A typed range that can be resolved to a byte range through a `TextFile`.
+/// Implemented by `CharRange`, `ByteRange` and `LineRange`.
+pub trait TypedRange {
+    /// Resolves the range to a byte range, checking that the range is valid for the text file
+    fn to_byterange(&self, textfile: &TextFile) -> Result<ByteRange, Error>;
+}
+
+impl TypedRange for CharRange {
+    fn to_byterange(&self, textfile: &TextFile) -> Result<ByteRange, Error> {
+        textfile.check_order(self.begin.0, self.end.0)?;
+        if self.end.0 > textfile.len() {
+            return Err(Error::OutOfBoundsError {
+                begin: self.begin.0 as isize,
+                end: self.end.0 as isize,
+            });
+        }
+        Ok(ByteRange {
+            begin: textfile.to_bytepos(self.begin)?,
+            end: textfile.to_bytepos(self.end)?,
+        })
+    }
+}
+
+impl TypedRange for ByteRange {
+    fn to_byterange(&self, textfile: &TextFile) -> Result<ByteRange, Error> {
+        textfile.check_order(self.begin.0, self.end.0)?;
+        //validates the character boundaries
+        textfile.to_charpos(self.begin)?;
+        textfile.to_charpos(self.end)?;
+        Ok(*self)
+    }
+}
+
+impl TypedRange for LineRange {
+    fn to_byterange(&self, textfile: &TextFile) -> Result<ByteRange, Error> {
+        textfile.check_order(self.begin.0, self.end.0)?;
+        Ok(ByteRange {
+            begin: textfile.line_bytepos(self.begin)?,
+            end: textfile.line_bytepos(self.end)?,
+        })
+    }
+}
+
+impl TextFile {
+    /// Converts a character position to a byte position
+    pub fn to_bytepos(&self, charpos: CharPos) -> Result<BytePos, Error> {
+        self.chars_to_bytes(charpos.0).map(BytePos)
+    }
+
+    /// Converts a byte position to a character position, returns `Error::InvalidUtf8Byte` if the byte is not at a character boundary
+    pub fn to_charpos(&self, bytepos: BytePos) -> Result<CharPos, Error> {
+        self.bytes_to_chars(bytepos.0).map(CharPos)
+    }
+
+    /// Returns the byte position where a line begins.
+    /// This will return an `Error::NoLineIndex` if no line index was computed/loaded.
+    pub fn line_bytepos(&self, line: LineNo) -> Result<BytePos, Error> {
+        if line.0 > isize::MAX as usize {
+            return Err(Error::OutOfBoundsError {
+                begin: isize::MAX,
+                end: 0,
+            });
+        }
+        self.line_to_bytes(line.0 as isize).map(BytePos)
+    }
+
+    /// Returns the character position where a line begins.
+    /// This will return an `Error::NoLineIndex` if no line index was computed/loaded.
+    pub fn line_charpos(&self, line: LineNo) -> Result<CharPos, Error> {
+        self.to_charpos(self.line_bytepos(line)?)
+    }
+
+    /// Returns the line a character position is on.
+    /// This will return an `Error::NoLineIndex` if no line index was computed/loaded.
+    pub fn line_of(&self, charpos: CharPos) -> Result<LineNo, Error> {
+        self.char_to_line(charpos.0).map(LineNo)
+    }
+
+    /// Returns the line a byte position is on.
+    /// This will return an `Error::NoLineIndex` if no line index was computed/loaded.
+    pub fn line_of_byte(&self, bytepos: BytePos) -> Result<LineNo, Error> {
+        self.byte_to_line(bytepos.0).map(LineNo)
+    }
+
+    /// Converts any typed range to a character range
+    pub fn to_charrange(&self, range: impl TypedRange) -> Result<CharRange, Error> {
+        let byterange = range.to_byterange(self)?;
+        Ok(CharRange {
+            begin: self.to_charpos(byterange.begin)?,
+            end: self.to_charpos(byterange.end)?,
+        })
+    }
+
+    /// Returns a text fragment for a typed range (`CharRange`, `ByteRange` or `LineRange`).
+    /// The fragment must already be in memory or an Error::NotLoaded will be returned.
+    /// Use `get_or_load_typed()` instead if the fragment might not be loaded yet.
+    pub fn get_typed(&self, range: impl TypedRange) -> Result<&str, Error> {
+        let byterange = range.to_byterange(self)?;
+        self.get_byterange_unchecked(byterange.begin.0, byterange.end.0)
+    }
+
+    /// Returns a text fragment for a typed range (`CharRange`, `ByteRange` or `LineRange`).
+    /// The fragment will be loaded from disk into memory if needed.
+    pub fn get_or_load_typed(&mut self, range: impl TypedRange) -> Result<&str, Error> {
+        let byterange = range.to_byterange(self)?;
+        self.get_or_load_bytes(byterange.begin.0, byterange.end.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+
+    #[test]
+    pub fn test024_typed_conversions() {
+        let file = setup_unicode();
+        let textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        assert_eq!(textfile.to_bytepos(CharPos(2)).unwrap(), BytePos(4));
+        assert_eq!(textfile.to_charpos(BytePos(4)).unwrap(), CharPos(2));
+        assert!(matches!(
+            textfile.to_charpos(BytePos(3)),
+            Err(Error::InvalidUtf8Byte(3))
+        ));
+        assert_eq!(textfile.line_bytepos(LineNo(1)).unwrap(), BytePos(1));
+        assert_eq!(textfile.line_charpos(LineNo(2)).unwrap(), CharPos(5));
+        assert_eq!(textfile.line_of(CharPos(3)).unwrap(), LineNo(1));
+        assert_eq!(textfile.line_of_byte(BytePos(7)).unwrap(), LineNo(1));
+        assert_eq!(
+            textfile
+                .to_charrange(LineRange::new(LineNo(1), LineNo(2)))
+                .unwrap(),
+            CharRange::new(CharPos(1), CharPos(5))
+        );
+    }
+
+    #[test]
+    pub fn test024_get_or_load_typed() {
+        let file = setup_unicode();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        assert_eq!(
+            textfile
+                .get_or_load_typed(CharRange::new(CharPos(1), CharPos(4)))
+                .expect("text should exist"),
+            "第一条"
+        );
+        assert_eq!(
+            textfile
+                .get_typed(ByteRange::new(BytePos(4), BytePos(7)))
+                .expect("text should exist"),
+            "一"
+        );
+        assert_eq!(
+            textfile
+                .get_or_load_typed(LineRange::new(LineNo(1), LineNo(2)))
+                .expect("text should exist"),
+            "第一条\n"
+        );
+        assert!(matches!(
+            textfile.get_or_load_typed(ByteRange::new(BytePos(2), BytePos(7))),
+            Err(Error::InvalidUtf8Byte(2))
+        ));
+        assert!(textfile
+            .get_or_load_typed(CharRange::new(CharPos(4), CharPos(1)))
+            .is_err());
+    }
+}
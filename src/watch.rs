@@ -0,0 +1,285 @@
+/*
+TextFrame
+  by Maarten van Gompel <proycon@anaproy.nl>
+  Digital Infrastructure, KNAW Humanities Cluster
+  licensed under the GNU General Public Licence v3
+*/
+
+//! Watching text files on disk for modifications (requires the `watch` feature)
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::{Error, TextFile};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+/// Determines what happens when a watched text file is modified
+pub enum WatchPolicy {
+    /// Mark the text file as stale on any modification, all subsequent loads return `Error::SourceModified` until `TextFile::reload()` is called.
+    #[default]
+    Invalidate,
+
+    /// If the file was only appended to, extend the index (see `TextFile::refresh_appended()`), otherwise mark the text file as stale.
+    RefreshAppended,
+}
+
+/// Callback that is invoked (from the watcher thread) whenever a modification of the text file is noticed
+pub type WatchCallback = Box<dyn Fn(&Path) + Send + Sync>;
+
+/// Holds the state of a watched text file
+pub(crate) struct FileWatcher {
+    /// The underlying (platform-specific) watcher, watching stops when this is dropped
+    _watcher: RecommendedWatcher,
+
+    /// Set by the watcher thread when a modification was noticed, cleared once handled
+    changed: Arc<AtomicBool>,
+
+    /// Set once a modification was noticed that could not be handled by the policy
+    stale: bool,
+
+    policy: WatchPolicy,
+}
+
+impl FileWatcher {
+    fn new(
+        path: &Path,
+        policy: WatchPolicy,
+        callback: Option<WatchCallback>,
+    ) -> Result<Self, Error> {
+        let changed = Arc::new(AtomicBool::new(false));
+        let watched: PathBuf = path.to_path_buf();
+        let changed2 = changed.clone();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                if let Ok(event) = event {
+                    if matches!(
+                        event.kind,
+                        EventKind::Modify(_) | EventKind::Remove(_) | EventKind::Create(_)
+                    ) {
+                        changed2.store(true, Ordering::SeqCst);
+                        if let Some(callback) = callback.as_ref() {
+                            callback(watched.as_path());
+                        }
+                    }
+                }
+            })
+            .map_err(|e| Error::IOError(std::io::Error::other(e)))?;
+        watcher
+            .watch(path, RecursiveMode::NonRecursive)
+            .map_err(|e| Error::IOError(std::io::Error::other(e)))?;
+        Ok(Self {
+            _watcher: watcher,
+            changed,
+            stale: false,
+            policy,
+        })
+    }
+}
+
+impl TextFile {
+    /// Starts watching the text file on disk for modifications (inotify-based on Linux). What happens
+    /// on modification is determined by the policy, changes are handled lazily by the next call that
+    /// loads text. An optional callback is invoked from the watcher thread whenever a modification is noticed.
+    ///
+    /// Offsets relative to the end of the text are resolved before pending modifications are handled,
+    /// call `check_watched()` first if they should take text appended in the meantime into account.
+    ///
+    /// Note that only the file itself is watched, if it is replaced by another file (e.g. by renaming) that is not detected.
+    pub fn watch(
+        &mut self,
+        policy: WatchPolicy,
+        callback: Option<WatchCallback>,
+    ) -> Result<(), Error> {
+        self.watcher = Some(FileWatcher::new(self.path.as_path(), policy, callback)?);
+        Ok(())
+    }
+
+    /// Stops watching the text file
+    pub fn unwatch(&mut self) {
+        self.watcher = None;
+    }
+
+    /// Returns true if the text file is being watched
+    pub fn is_watched(&self) -> bool {
+        self.watcher.is_some()
+    }
+
+    /// Returns true if a watched text file was modified and can no longer be loaded from, use `reload()` to recover.
+    /// Modifications that have been noticed but not handled yet are not reflected here.
+    pub fn is_stale(&self) -> bool {
+        self.watcher.as_ref().is_some_and(|watcher| watcher.stale)
+    }
+
+    /// Handles any pending modifications according to the watch policy, returns `Error::SourceModified` if the text file is stale.
+    /// This is done automatically whenever text is loaded from disk, but streaming methods that take `&self` (such as `find_iter()`)
+    /// can not do so and return `Error::SourceModified` if a modification is pending, call this first to handle it.
+    pub fn check_watched(&mut self) -> Result<(), Error> {
+        let Some(watcher) = self.watcher.as_mut() else {
+            return Ok(());
+        };
+        if watcher.changed.swap(false, Ordering::SeqCst) && !watcher.stale {
+            match watcher.policy {
+                WatchPolicy::Invalidate => watcher.stale = true,
+                WatchPolicy::RefreshAppended => match self.refresh_appended() {
+                    Ok(_) => {}
+                    Err(Error::SourceModified { .. }) => {
+                        if let Some(watcher) = self.watcher.as_mut() {
+                            watcher.stale = true;
+                        }
+                    }
+                    Err(e) => {
+                        //the modification may still be in progress (e.g. a partially written character), handle it again next time
+                        if let Some(watcher) = self.watcher.as_mut() {
+                            watcher.changed.store(true, Ordering::SeqCst);
+                        }
+                        return Err(e);
+                    }
+                },
+            }
+        }
+        if self.is_stale() {
+            Err(Error::SourceModified {
+                begin: 0,
+                end: self.positionindex.bytesize,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns `Error::SourceModified` if the text file is stale or a modification is pending, for methods that can not handle modifications themselves
+    pub(crate) fn check_watched_pending(&self) -> Result<(), Error> {
+        match self.watcher.as_ref() {
+            Some(watcher) if watcher.stale || watcher.changed.load(Ordering::SeqCst) => {
+                Err(Error::SourceModified {
+                    begin: 0,
+                    end: self.positionindex.bytesize,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Clears the stale state after a reload
+    pub(crate) fn reset_watched(&mut self) {
+        if let Some(watcher) = self.watcher.as_mut() {
+            watcher.changed.store(false, Ordering::SeqCst);
+            watcher.stale = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+
+    /// Waits until the watcher noticed a change
+    fn wait_for_change(textfile: &TextFile) {
+        let start = Instant::now();
+        while !textfile
+            .watcher
+            .as_ref()
+            .expect("watcher must exist")
+            .changed
+            .load(Ordering::SeqCst)
+        {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "no change noticed"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    pub fn test017_watch_invalidate() {
+        let mut file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let notified = Arc::new(AtomicBool::new(false));
+        let notified2 = notified.clone();
+        textfile
+            .watch(
+                WatchPolicy::Invalidate,
+                Some(Box::new(move |_| notified2.store(true, Ordering::SeqCst))),
+            )
+            .expect("watch must work");
+        assert_eq!(
+            textfile.get_or_load(1, 10).expect("text should exist"),
+            "Article 1"
+        );
+        assert_eq!(
+            textfile.get_or_load_lines(1, 2).expect("text should exist"),
+            "Article 1\n"
+        );
+        file.write_all(b"Article 5\n").expect("write must work");
+        file.flush().expect("flush must work");
+        wait_for_change(&textfile);
+        assert!(notified.load(Ordering::SeqCst));
+        assert!(matches!(
+            textfile.get_or_load(1, 10),
+            Err(Error::SourceModified { .. })
+        ));
+        //also for lines that were loaded before
+        assert!(matches!(
+            textfile.get_or_load_lines(1, 2),
+            Err(Error::SourceModified { .. })
+        ));
+        assert!(textfile.is_stale());
+        textfile.reload().expect("reload must work");
+        assert!(!textfile.is_stale());
+        assert_eq!(
+            textfile.get_or_load(-10, 0).expect("text should exist"),
+            "Article 5\n"
+        );
+    }
+
+    #[test]
+    pub fn test017_watch_refresh_appended() {
+        let mut file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        textfile
+            .watch(WatchPolicy::RefreshAppended, None)
+            .expect("watch must work");
+        file.write_all(b"Article 5\n").expect("write must work");
+        file.flush().expect("flush must work");
+        wait_for_change(&textfile);
+        //streaming methods do not handle pending modifications themselves
+        assert!(matches!(
+            textfile.find_iter("Article 5").next(),
+            Some(Err(Error::SourceModified { .. }))
+        ));
+        textfile.check_watched().expect("check must work");
+        assert_eq!(
+            textfile.get_or_load(-10, 0).expect("text should exist"),
+            "Article 5\n"
+        );
+        assert_eq!(textfile.find_iter("Article 5").count(), 1);
+        assert!(!textfile.is_stale());
+    }
+
+    #[test]
+    pub fn test017_watch_load_frame() {
+        let mut file = setup_ascii();
+        let mut textfile =
+            TextFile::new(file.path(), None, Default::default()).expect("file must load");
+        let len = textfile.len();
+        textfile
+            .watch(WatchPolicy::RefreshAppended, None)
+            .expect("watch must work");
+        file.write_all(b"Article 5\n").expect("write must work");
+        file.flush().expect("flush must work");
+        wait_for_change(&textfile);
+        //handled by loading text
+        textfile.load(0, 10).expect("load must work");
+        assert_eq!(textfile.len(), len + 10);
+        assert!(!textfile.is_stale());
+    }
+}